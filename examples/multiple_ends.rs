@@ -2,7 +2,8 @@
 // effectors in action together, just note that the extra end effector is
 // NOT like a pole target, at least with a normal weight. It freaks out
 // a little if you move the end effector in question to a place the chain
-// can't reach, or if the whole chain is tensioned.
+// can't reach, or if the whole chain is tensioned. If you want to control
+// which way a chain bends, use a PoleTarget instead (see simple.rs).
 use bevy::{input::mouse::AccumulatedMouseScroll, prelude::*};
 use bevy_fabrik_solver::*;
 
@@ -44,6 +45,7 @@ fn setup(
         length: joint_length,
         visual_offset: Vec3::Y * joint_length * 0.5,
         anchor_offset: Vec3::ZERO,
+        ..Default::default()
     }, RotationConstraint{
             identity: Quat::IDENTITY,
             weight: 1.0,
@@ -82,6 +84,7 @@ fn setup(
         Mesh3d(meshes.add(Sphere::new(joint_length * 0.2))),
         MeshMaterial3d(materials.add(end_material.clone())),
         Transform::from_xyz(-0.25, 0.8, 0.0),
+        EndEffector::default(),
     )).observe(translate_on_drag).observe(hover_scroll).id();
 
     let end2 = commands.spawn((
@@ -89,6 +92,7 @@ fn setup(
         Mesh3d(meshes.add(Sphere::new(joint_length * 0.2))),
         MeshMaterial3d(materials.add(end_material.clone())),
         Transform::from_xyz(0.25, 0.9, 0.0),
+        EndEffector::default(),
     )).observe(translate_on_drag).observe(hover_scroll).id();
 
         
@@ -104,7 +108,7 @@ fn setup(
             // it'll freak out. Too low and it'll be all floaty and weird.
             // Also, you can add an end effector like this if you want
             // to change the end effector settings.
-            weight: 0.01, 
+            position_weight: 0.01,
             ..Default::default()
         }
     )).observe(translate_on_drag).observe(hover_scroll).id();
@@ -122,6 +126,7 @@ fn setup(
             length: joint_length,
             visual_offset: Vec3::Y * joint_length * 0.5,
             anchor_offset: Vec3::ZERO,
+            ..Default::default()
         },
         mesh.clone(),
         Transform::from_xyz(0.0, 0.0, 0.0),
@@ -146,6 +151,18 @@ fn setup(
                         mesh.clone(),
                         Transform::from_xyz(0.0, joint_length, 0.0),
                         EEJoint(end1),
+                        //a joint can have more than one end effector, this one stays put and
+                        //End 1 only gets part of the way when it's dragged away from it
+                        related!(JointEffectors[(
+                            Name::new("End 4"),
+                            Mesh3d(meshes.add(Sphere::new(joint_length * 0.18))),
+                            MeshMaterial3d(materials.add(end_material.clone())),
+                            Transform::from_xyz(-0.45, 0.7, 0.0),
+                            EndEffector{
+                                position_weight: 0.5,
+                                ..Default::default()
+                            },
+                        )]),
                     )])
                 )])
             ), (
@@ -16,6 +16,9 @@
 // of both translational and rotational (only useful if a joint has more
 // then one thing to point at).
 
+// A Pole Target can also be linked to a joint, it bends the joints
+// below it towards itself, which is useful for knees and elbows.
+
 // Includes some functions at the bottom to conveniently drag around the
 // effectors.
 
@@ -62,6 +65,8 @@ fn setup(
             visual_offset: Vec3::Y * joint_length * 0.5,
             //the offset of the current base from the parent's end, obviously doesn't do anything to the bottom joint.
             anchor_offset: Vec3::ZERO,
+            //joints point along their local Y and face their local Z by default, bone_axis and secondary_axis change that
+            ..Default::default()
         },
         RotationConstraint{
             identity: Quat::IDENTITY,
@@ -110,9 +115,30 @@ fn setup(
         // you see there are the default.
         EndEffector{
             joint_center: false,
-            joint_copy_rotation: false,
-            weight: 1.0,
+            position_weight: 1.0,
+            rotation_weight: 0.0,
+            mode: EffectorMode::Reach,
+            chain_length: None,
+        }
+    )).observe(translate_on_drag).observe(hover_scroll).id();
+
+    let pole_material = StandardMaterial{
+        base_color: Color::srgb_u8(120, 230, 144),
+        unlit: true,
+        ..Default::default()
+    };
+
+    //The Pole Target, the chain will bend towards this.
+    let pole = commands.spawn((
+        Name::new("Pole"),
+        Mesh3d(meshes.add(Sphere::new(joint_length * 0.15))),
+        MeshMaterial3d(materials.add(pole_material)),
+        Transform::from_xyz(0.0, 0.5, 0.5),
+        PoleTarget{
             joint: None,
+            //bends the whole chain, a knee or elbow would use Some(2)
+            chain_length: None,
+            weight: 0.5,
         }
     )).observe(translate_on_drag).observe(hover_scroll).id();
 
@@ -134,6 +160,7 @@ fn setup(
             length: joint_length,
             visual_offset: Vec3::Y * joint_length * 0.5,
             anchor_offset: Vec3::ZERO,
+            ..Default::default()
         },
         mesh.clone(),
         Transform::from_xyz(0.0, 0.0, 0.0),
@@ -158,6 +185,7 @@ fn setup(
                         mesh.clone(),
                         Transform::from_xyz(0.0, joint_length, 0.0),
                         EEJoint(end), //this component is important
+                        PoleJoint(pole),
                     )]
                 )]
             )]
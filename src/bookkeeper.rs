@@ -1,6 +1,6 @@
 
 
-use crate::{IkGlobalSettings, JointParent};
+use crate::{HingeConstraint, IkChainInvalid, IkLockRotation, IkPinned, JointLock, IkChainSettings, IkDisabled, IkError, IkInvalid, IkGlobalSettings, IkSeedPose, IkWeight, JointChain, JointChildren, JointParent, RotationConstraint};
 
 use super::{
     Joint,
@@ -8,11 +8,16 @@ use super::{
     JointTransform,
     EndEffector,
     EEJoint,
+    EffectorJoint,
+    JointEffectors,
+    PoleTarget,
+    PoleJoint,
     Base,
     BaseJoint,
 };
 
-use bevy::{math::Affine3A, prelude::*};
+use bevy::{ecs::{entity::EntityHashSet, lifecycle::HookContext, world::DeferredWorld}, math::Affine3A, prelude::*};
+use rayon::prelude::*;
 
 
 
@@ -20,80 +25,147 @@ pub fn joint_hooks(
     world: &mut World,
 ){
 
+    //anything that changes the shape of a chain marks the chains to be rebuilt
     world.register_component_hooks::<Joint>()
-    .on_remove(
-        |mut world, context|{
-            world.resource_mut::<JointBookkeeping>().joints.lock().unwrap().remove(&context.entity);
-        }
-    );
+        .on_insert(mark_chains_dirty)
+        .on_replace(mark_chains_dirty);
+
+    //the relationship already uses insert and replace, add and remove are enough here
+    world.register_component_hooks::<JointParent>()
+        .on_add(mark_chains_dirty)
+        .on_remove(mark_chains_dirty);
+
+    world.register_component_hooks::<JointChildren>()
+        .on_add(mark_chains_dirty)
+        .on_remove(mark_chains_dirty);
+
+    //same as the joint relationship, retargeting an effector is caught by build_chains
+    world.register_component_hooks::<EffectorJoint>()
+        .on_add(mark_chains_dirty)
+        .on_remove(mark_chains_dirty);
 
-    //removes end effector from book, and removes the ee joint on the other end
+    world.register_component_hooks::<JointEffectors>()
+        .on_add(mark_chains_dirty)
+        .on_remove(mark_chains_dirty);
+
+    //detaches the end effector, and removes the ee joint on the other end if it was attached that way
     world.register_component_hooks::<EndEffector>()
-        .on_add(|mut world, context|{
-            
-            let Some(joint) = world.get::<EndEffector>(context.entity).unwrap().joint else { return };
-            world.commands().entity(joint).insert(EEJoint(context.entity));
-        
-        })
         .on_remove(
             |mut world, context|{
-                let (ee, _) = world.resource_mut::<JointBookkeeping>().ends.write().unwrap().remove(&context.entity).unwrap();
-                
-                let Some(joint) = ee.joint else { return };
-                world.commands().entity(joint).try_remove::<EEJoint>();
+                let Some(joint) = world.get::<EffectorJoint>(context.entity).map(|effector_joint| effector_joint.0) else { return };
+                if world.get::<EEJoint>(joint).is_some_and(|ee_joint| ee_joint.0 == context.entity) {
+                    world.commands().entity(joint).try_remove::<EEJoint>();
+                }
+                world.commands().entity(context.entity).try_remove::<EffectorJoint>();
             }
         );
 
+    //ee joint only attaches its end effector, the relationship does the rest
     world.register_component_hooks::<EEJoint>()
-        .on_add(|mut world, context|{
+        .on_insert(|mut world, context|{
             let effector = world.get::<EEJoint>(context.entity).unwrap().0;
-            let (mut j_c, mut j_c_r, mut w) = (false, false, 1.0);
-            if let Some(ee) = world.get::<EndEffector>(effector) {
-                j_c = ee.joint_center;
-                j_c_r = ee.joint_copy_rotation;
-                w = ee.weight;
+            //EffectorJoint requires an EndEffector, so anything it's pointed at becomes one. That's kept, but noted
+            if world.get_entity(effector).is_ok_and(|effector| !effector.contains::<EndEffector>())
+                && let Some(mut joint_bookkeeper) = world.get_resource_mut::<JointBookkeeping>()
+            {
+                joint_bookkeeper.implicit_effectors.insert(context.entity, effector);
+            }
+            world.commands().entity(effector).try_insert(EffectorJoint(context.entity));
+            mark_chains_dirty(world, context);
+        })
+        .on_replace(
+            |mut world, context|{
+                if let Some(mut joint_bookkeeper) = world.get_resource_mut::<JointBookkeeping>() {
+                    joint_bookkeeper.implicit_effectors.remove(&context.entity);
+                }
+                let effector = world.get::<EEJoint>(context.entity).unwrap().0;
+                //the end effector might have been attached to another joint since
+                if world.get::<EffectorJoint>(effector).is_some_and(|effector_joint| effector_joint.0 == context.entity) {
+                    world.commands().entity(effector).try_remove::<EffectorJoint>();
+                }
+                mark_chains_dirty(world, context);
+            }
+        );
+
+
+    //pole targets work the same way as end effectors
+    world.register_component_hooks::<PoleTarget>()
+        .on_add(|mut world, context|{
+            let Some(joint) = world.get::<PoleTarget>(context.entity).unwrap().joint else { return };
+            world.commands().entity(joint).try_insert(PoleJoint(context.entity));
+        })
+        .on_remove(
+            |mut world, context|{
+                let Some(joint) = world.get::<PoleTarget>(context.entity).unwrap().joint else { return };
+                //the joint might have been given another pole since
+                if world.get::<PoleJoint>(joint).is_some_and(|pole_joint| pole_joint.0 == context.entity) {
+                    world.commands().entity(joint).try_remove::<PoleJoint>();
+                }
+            }
+        );
+
+    //like ee joint, re-inserting it with another pole moves the pole target over
+    world.register_component_hooks::<PoleJoint>()
+        .on_insert(|mut world, context|{
+            let pole = world.get::<PoleJoint>(context.entity).unwrap().0;
+            let (mut c_l, mut w) = (None, 1.0);
+            if let Some(pt) = world.get::<PoleTarget>(pole) {
+                c_l = pt.chain_length;
+                w = pt.weight;
             }
 
-            world.commands().entity(effector).insert(
-                EndEffector{
+            world.commands().entity(pole).try_insert(
+                PoleTarget{
                     joint: Some(context.entity),
-                    joint_center: j_c,
-                    joint_copy_rotation: j_c_r,
+                    chain_length: c_l,
                     weight: w,
                 });
-        
+            mark_chains_dirty(world, context);
         })
-        .on_remove(
+        .on_replace(
             |mut world, context|{
-                
-                let end = world.get::<EEJoint>(context.entity).unwrap().0;
-                
-                
-                world.commands().entity(end).try_remove::<EndEffector>();
+                let joint = context.entity;
+                let pole = world.get::<PoleJoint>(joint).unwrap().0;
+                //only detached once the new PoleJoint is in, re-inserting the same pole keeps it
+                world.commands().queue(move |world: &mut World| {
+                    if world.get::<PoleJoint>(joint).is_some_and(|pole_joint| pole_joint.0 == pole)
+                        || world.get::<PoleTarget>(pole).is_none_or(|pole_target| pole_target.joint != Some(joint))
+                    {
+                        return;
+                    }
+                    if let Ok(mut pole) = world.get_entity_mut(pole) {
+                        pole.remove::<PoleTarget>();
+                    }
+                });
+                mark_chains_dirty(world, context);
             }
         );
 
-
     //same as above
     world.register_component_hooks::<Base>()
     .on_add(|mut world, context|{
-        let joint = world.get::<Base>(context.entity).unwrap().0;
-        world.commands().entity(joint).insert(BaseJoint(context.entity));
+        let joint = world.get::<Base>(context.entity).unwrap().joint;
+        world.commands().entity(joint).try_insert(BaseJoint(context.entity));
         //handles only insertion of basejoint
         
     })
     .on_remove(
         |mut world, context|{
-            let (base, _) = world.resource_mut::<JointBookkeeping>().bases.write().unwrap().remove(&context.entity).unwrap();
-            world.commands().entity(base.0).try_remove::<BaseJoint>();
+            let joint = world.get::<Base>(context.entity).unwrap().joint;
+            world.commands().entity(joint).try_remove::<BaseJoint>();
         }
     );
 
     world.register_component_hooks::<BaseJoint>()
         .on_add(|mut world, context|{
             let base = world.get::<BaseJoint>(context.entity).unwrap().0;
-            world.commands().entity(base).insert(
-                Base(context.entity));
+            //keeps the settings of a base that already exists
+            let base_c = world.get::<Base>(base).copied().unwrap_or_default();
+            world.commands().entity(base).try_insert(
+                Base{
+                    joint: context.entity,
+                    ..base_c
+                });
             //handles only insertion of basejoint
         
         })
@@ -105,18 +177,35 @@ pub fn joint_hooks(
                 
                 world.commands().entity(base).try_remove::<Base>();
             }
-        );
+        )
+        .on_insert(mark_chains_dirty)
+        .on_replace(mark_chains_dirty);
 
     
 }
 
 
+fn mark_chains_dirty(mut world: DeferredWorld, _: HookContext){
+    if let Some(mut joint_bookkeeper) = world.get_resource_mut::<JointBookkeeping>() {
+        joint_bookkeeper.dirty = true;
+    }
+}
+
+
 pub fn collect_joint_transforms(
-        mut transforms_q: Query<(&mut JointTransform, Entity), Changed<Transform>>,
+        mut transforms_q: Query<(&mut JointTransform, Entity, Ref<Transform>)>,
         helper: TransformHelper,
+        global_settings: Res<IkGlobalSettings>,
 ){
-    for (mut jt, entity) in transforms_q.iter_mut(){
-        let srt = helper.compute_global_transform(entity).unwrap().to_scale_rotation_translation();
+    let reseed = global_settings.seed_pose == IkSeedPose::Input;
+    for (mut jt, entity, transform) in transforms_q.iter_mut(){
+        //a parent's animation moves its children without changing their Transform, so everything gets read when reseeding
+        if !reseed && !transform.is_changed() {
+            continue;
+        }
+        //a joint whose hierarchy can't be resolved (e.g. a parent without a Transform) just keeps its last pose
+        let Ok(global) = helper.compute_global_transform(entity) else { continue };
+        let srt = global.to_scale_rotation_translation();
         jt.scale = srt.0;
         jt.rotation = srt.1;
         jt.translation = srt.2;
@@ -124,83 +213,285 @@ pub fn collect_joint_transforms(
 }
 
 
+//gives joints spawned with ChildOf a JointParent, if their parent is also a joint. A joint with a BaseJoint
+//starts its own tree, closing a loop between two bases needs an explicit JointParent
 #[allow(clippy::type_complexity)]
 pub fn bookkeep_joints_start(
-    joint_bookkeeper: Res<JointBookkeeping>,
-    joints_q: Query<(&Joint, &JointTransform, Entity)>,
-    end_effectors_q: Query<(&EndEffector, &JointTransform, Entity)>,
-    bases_q: Query<(&Base, &JointTransform, Entity)>,
-    parent_setup: Query<(Entity, &ChildOf), Added<Joint>>,
+    parent_setup: Query<(Entity, &ChildOf), (Added<Joint>, Without<BaseJoint>)>,
     joint_q: Query<&Joint>,
     mut commands: Commands,
 ){
-    //Joints first, could make par iter
-    for (joint, jt, entity) in joints_q.iter(){
-        joint_bookkeeper.joints.lock().unwrap().insert(entity, (*joint, *jt));
+    for (entity, parent) in parent_setup.iter(){
+        //makes sure the parent actually is a joint
+        let Ok(_) = joint_q.get(parent.0) else {continue};
+        commands.entity(entity).try_insert_if_new(JointParent(parent.0));
     }
 
-    for (end_effector, jt, entity) in end_effectors_q.iter(){
-        joint_bookkeeper.ends.write().unwrap().insert(entity, (*end_effector, *jt));
+}
+
+//flattens every tree of joints into a chain, all of them when the hooks have marked them as changed
+#[allow(clippy::type_complexity)]
+pub fn build_chains(
+    mut joint_bookkeeper: ResMut<JointBookkeeping>,
+    root_joints: Query<Entity, (With<Joint>, Without<JointParent>)>,
+    parents_q: Query<&JointParent>,
+    children_q: Query<&JointChildren>,
+    links_q: Query<(Option<&JointEffectors>, Option<&EEJoint>, Option<&PoleJoint>, Option<&BaseJoint>)>,
+    effectors_q: Query<&EndEffector>,
+    reparented_q: Query<(Entity, Option<&JointParent>, Option<&EffectorJoint>), Or<(Changed<JointParent>, Changed<EffectorJoint>)>>,
+){
+    if joint_bookkeeper.dirty {
+        joint_bookkeeper.dirty = false;
+
+        //sorted, so chains are always solved and written back in the same order
+        let mut roots: Vec<Entity> = root_joints.iter().collect();
+        roots.sort();
+
+        joint_bookkeeper.chains = roots.into_iter().map(|root| JointChain::build(root, &children_q, &links_q, &effectors_q)).collect();
+        return;
     }
 
-    for (base, jt, entity) in bases_q.iter() {
-        joint_bookkeeper.bases.write().unwrap().insert(entity, (*base, *jt));
+    //moving a joint or end effector from one joint to another doesn't add or remove anything, so the hooks don't see it.
+    //Only the chain it was in and the chain it's in now get rebuilt
+    let mut rebuild = Vec::new();
+    for (entity, parent, effector_joint) in reparented_q.iter() {
+        let owner = joint_bookkeeper.chains.iter().find(|chain| {
+            chain.entities.contains(&entity) || chain.effector_entities.iter().any(|effectors| effectors.contains(&entity))
+        });
+        rebuild.extend(owner.map(|chain| chain.root));
+
+        //walks down to the current root, stopping if it goes around a cycle
+        let mut current = parent.map(|parent| parent.0).or(effector_joint.map(|effector_joint| effector_joint.0)).unwrap_or(entity);
+        let mut seen = EntityHashSet::default();
+        while seen.insert(current) && let Ok(parent) = parents_q.get(current) {
+            current = parent.0;
+        }
+        rebuild.push(current);
     }
-    for (entity, parent) in parent_setup.iter(){
-        //makes sure the parent actually is a joint
-        let Ok(_) = joint_q.get(parent.0) else {continue};
-        commands.entity(entity).try_insert_if_new(JointParent(parent.0));
+
+    for chain in joint_bookkeeper.chains.iter_mut() {
+        if rebuild.contains(&chain.root) {
+            *chain = JointChain::build(chain.root, &children_q, &links_q, &effectors_q);
+        }
+    }
+}
+
+//copies this frame's joints, effectors, poles, bases and settings into the chains, one chain per thread
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn gather_chains(
+    mut joint_bookkeeper: ResMut<JointBookkeeping>,
+    global_settings: Res<IkGlobalSettings>,
+    joints_q: Query<(&Joint, &JointTransform, &Transform, Option<&RotationConstraint>, Option<&HingeConstraint>, Has<IkPinned>, Has<IkLockRotation>)>,
+    parents_q: Query<&ChildOf, With<JointTransform>>,
+    helper: TransformHelper,
+    effectors_q: Query<(&EndEffector, &JointTransform)>,
+    poles_q: Query<(&PoleTarget, &JointTransform)>,
+    bases_q: Query<(&Base, &JointTransform)>,
+    chain_settings_q: Query<&IkChainSettings>,
+    weight_q: Query<AnyOf<(&IkWeight, &IkDisabled)>>,
+){
+    let weight_of = |(weight, disabled): (Option<&IkWeight>, Option<&IkDisabled>)| {
+        if disabled.is_some() {
+            0.0
+        } else {
+            weight.map_or(1.0, |weight| weight.0.clamp(0.0, 1.0))
+        }
+    };
+
+    let reseed = global_settings.seed_pose == IkSeedPose::Input;
+
+    joint_bookkeeper.chains.par_iter_mut().for_each(|chain| {
+        //only the first problem is kept, the chain gets skipped either way
+        chain.invalid = None;
+        let mut reorder = false;
+        for i in 0..chain.len() {
+            let entity = chain.entities[i];
+            let Ok((joint, jt, transform, constraint, hinge, pinned, lock_rotation)) = joints_q.get(entity) else {
+                chain.invalid.get_or_insert(IkError::MissingJoint(entity));
+                continue;
+            };
+            chain.fabrik.joints[i] = *joint;
+            //a Transform that's still what sync_transforms wrote only holds the blended pose, so the joint keeps its
+            //input pose and starts from last frame's solve instead of compounding the blend. When reseeding, a parent
+            //that animation moved since also moves the joint, even if its own Transform isn't animated
+            let parent_moved = reseed && chain.written_parents[i].is_some_and(|written| {
+                parents_q.get(entity).ok().and_then(|parent| helper.compute_global_transform(parent.0).ok()) != Some(written)
+            });
+            if chain.written_transforms[i] != Some(*transform) || parent_moved {
+                chain.fabrik.input_transforms[i] = *jt;
+                chain.fabrik.transforms[i] = *jt;
+                chain.written_transforms[i] = None;
+                chain.written_parents[i] = None;
+            }
+            chain.fabrik.constraints[i] = constraint.copied();
+            chain.fabrik.hinges[i] = hinge.copied();
+            chain.fabrik.locks[i] = if pinned {
+                JointLock::Pinned
+            } else if lock_rotation {
+                JointLock::Rotation
+            } else {
+                JointLock::Free
+            };
+
+            let effectors = &mut chain.fabrik.effectors[i];
+            let mut gathered = 0;
+            for effector in chain.effector_entities[i].iter() {
+                match effectors_q.get(*effector) {
+                    Ok((ee, jt)) => {
+                        //a new chain_length only changes which joints the forward reach moves, that's not worth a rebuild
+                        match effectors.get_mut(gathered) {
+                            Some(slot) => {
+                                reorder |= slot.0.chain_length != ee.chain_length;
+                                *slot = (*ee, *jt);
+                            }
+                            None => effectors.push((*ee, *jt)),
+                        }
+                        gathered += 1;
+                    }
+                    Err(_) => {
+                        chain.invalid.get_or_insert(IkError::MissingEndEffector{ joint: entity, effector: *effector });
+                    }
+                }
+            }
+            effectors.truncate(gathered);
+            chain.fabrik.poles[i] = chain.pole_entities[i].and_then(|pole| match poles_q.get(pole) {
+                Ok((pole, jt)) => Some(((*pole).into(), *jt)),
+                Err(_) => {
+                    chain.invalid.get_or_insert(IkError::MissingPoleTarget{ joint: entity, pole });
+                    None
+                }
+            });
+        }
+
+        for i in 0..chain.len() {
+            let entity = chain.entities[i];
+            chain.fabrik.bases[i] = chain.base_entities[i].and_then(|base| match bases_q.get(base) {
+                Ok((base_c, jt)) => Some(((*base_c).into(), *jt)),
+                Err(_) => {
+                    chain.invalid.get_or_insert(IkError::MissingBase{ joint: entity, base });
+                    None
+                }
+            });
+        }
+        if reorder {
+            chain.fabrik.refresh_forward_order();
+        }
+
+        //the root's base is the one the chain's weights fall back to
+        let base = chain.base_entities[0];
+
+        //settings can be on any base joint or base, the root's come first and a joint goes before its own base
+        let mut settings = (0..chain.len())
+            .filter(|i| *i == 0 || chain.base_entities[*i].is_some())
+            .flat_map(|i| [Some(chain.entities[i]), chain.base_entities[i]])
+            .flatten()
+            .find_map(|entity| chain_settings_q.get(entity).ok())
+            .copied()
+            .unwrap_or_default();
+        if weight_q.get(chain.root).is_ok_and(|(_, disabled)| disabled.is_some())
+            || base.is_some_and(|base| weight_q.get(base).is_ok_and(|(_, disabled)| disabled.is_some()))
+        {
+            settings.enabled = false;
+        }
+        chain.settings = settings;
+
+        //the nearest IkWeight or IkDisabled going from the joint down to the root joint, and then the root's base
+        let base_weight = base.and_then(|base| weight_q.get(base).ok()).map_or(1.0, weight_of);
+        for i in 0..chain.len() {
+            chain.weights[i] = match weight_q.get(chain.entities[i]) {
+                Ok(found) => weight_of(found),
+                Err(_) => chain.fabrik.parents[i].map_or(base_weight, |parent| chain.weights[parent]),
+            };
+        }
+    });
+}
+
+//marks chains that can't be solved with IkInvalid, and triggers IkChainInvalid when one breaks
+pub fn validate_chains(
+    joint_bookkeeper: Res<JointBookkeeping>,
+    invalid_q: Query<(Entity, &IkInvalid)>,
+    mut commands: Commands,
+){
+    //anything still marked that isn't the root of a broken chain anymore gets cleared, including old roots
+    //left behind when a rebuild changed the chain's root (re-parenting, or a base added or removed)
+    for (entity, _) in invalid_q.iter() {
+        if !joint_bookkeeper.chains.iter().any(|chain| chain.root == entity && chain.invalid.is_some()) {
+            commands.entity(entity).try_remove::<IkInvalid>();
+        }
     }
 
+    for chain in joint_bookkeeper.chains.iter() {
+        let Some(reason) = chain.invalid else { continue };
+        if invalid_q.get(chain.root).is_ok_and(|(_, invalid)| invalid.reason == reason) {
+            continue;
+        }
+
+        warn!("skipping ik chain {}: {}", chain.root, reason);
+        commands.entity(chain.root).try_insert(IkInvalid{ reason });
+        commands.trigger(IkChainInvalid{ entity: chain.root, reason });
+    }
 }
 
+//writes every solved chain back in one pass
 #[allow(clippy::type_complexity)]
 pub fn sync_transforms(
-    joint_bookkeeper: Res<JointBookkeeping>, 
+    mut joint_bookkeeper: ResMut<JointBookkeeping>,
     mut transforms_param_set: ParamSet<(
-        Query<(&mut Transform, Option<&ChildOf>), With<JointTransform>>,
+        Query<&mut Transform, With<JointTransform>>,
         TransformHelper,
     )>,
     mut joints_q: Query<&mut JointTransform>,
     parents_q: Query<&ChildOf, With<JointTransform>>,
 ) {
+    //root to leaf, so a joint's parent is already written by the time its own local transform is worked out
+    for chain in joint_bookkeeper.chains.iter_mut().filter(|chain| chain.invalid.is_none()) {
+        for (i, entity) in chain.entities.iter().enumerate() {
+            let weight = chain.weights[i];
+            //fully disabled joints are left alone, so animation or anything else can drive them
+            if weight <= 0.0 {
+                continue;
+            }
+            let Ok(mut joint_transform) = joints_q.get_mut(*entity) else { continue };
 
-    
-
+            let solved = chain.fabrik.transforms[i];
+            let input = chain.fabrik.input_transforms[i];
+            let jt = if weight >= 1.0 {
+                solved
+            } else {
+                JointTransform{
+                    scale: input.scale.lerp(solved.scale, weight),
+                    rotation: input.rotation.slerp(solved.rotation, weight),
+                    translation: input.translation.lerp(solved.translation, weight),
+                }
+            };
+            *joint_transform = jt;
 
-    //joints, dont really like having to lock the whole thing, but it doesnt seem possible to do something like [i], maybe it is ill have to find out
-    
-    for (entity, (_, jt)) in joint_bookkeeper.joints.lock().unwrap().iter() {
-        let maybe_parent = parents_q.get(*entity);
-        *joints_q.get_mut(*entity).unwrap() = *jt;
-        
-        if let Ok(parent) = maybe_parent{
-            
-            let new_gt = transforms_param_set.p1().compute_global_transform(parent.0).unwrap();
-            let new_affine = new_gt.affine().inverse();
-            let final_affine = new_affine * Affine3A::from_scale_rotation_translation(jt.scale, jt.rotation, jt.translation);
-            let srt = final_affine.to_scale_rotation_translation();
+            if let Ok(parent) = parents_q.get(*entity){
+                
+                let Ok(new_gt) = transforms_param_set.p1().compute_global_transform(parent.0) else { continue };
+                let new_affine = new_gt.affine().inverse();
+                let final_affine = new_affine * Affine3A::from_scale_rotation_translation(jt.scale, jt.rotation, jt.translation);
+                let srt = final_affine.to_scale_rotation_translation();
 
-            let new_transform = Transform::from_scale(srt.0).with_rotation(srt.1).with_translation(srt.2);
+                let new_transform = Transform::from_scale(srt.0).with_rotation(srt.1).with_translation(srt.2);
 
-            if new_transform.is_finite(){
-                *transforms_param_set.p0().get_mut(*entity).unwrap().0.bypass_change_detection() = new_transform;
-            }
-        }else{
-            
-            let new_transform = Transform::from_scale(jt.scale).with_rotation(jt.rotation).with_translation(jt.translation);
-            
-            if new_transform.is_finite(){
-                *transforms_param_set.p0().get_mut(*entity).unwrap().0 = new_transform;
+                if new_transform.is_finite() && let Ok(mut transform) = transforms_param_set.p0().get_mut(*entity){
+                    *transform.bypass_change_detection() = new_transform;
+                    chain.written_transforms[i] = Some(new_transform);
+                    chain.written_parents[i] = Some(new_gt);
+                }
+            }else{
+                
+                let new_transform = Transform::from_scale(jt.scale).with_rotation(jt.rotation).with_translation(jt.translation);
+                
+                if new_transform.is_finite() && let Ok(mut transform) = transforms_param_set.p0().get_mut(*entity){
+                    *transform = new_transform;
+                    chain.written_transforms[i] = Some(new_transform);
+                    chain.written_parents[i] = None;
+                }
             }
         }
     }
-
-    
-    
-        
-    
-    
 }
 
 pub fn force_gt(
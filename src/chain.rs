@@ -0,0 +1,423 @@
+use bevy::{ecs::entity::EntityHashSet, prelude::*};
+
+use super::{
+    Base,
+    BaseJoint,
+    EEJoint,
+    EndEffector,
+    HingeConstraint,
+    IkChainSettings,
+    IkChainState,
+    IkEffectorState,
+    IkError,
+    Joint,
+    JointChildren,
+    JointEffectors,
+    JointTransform,
+    PoleJoint,
+    PoleTarget,
+    RotationConstraint,
+};
+
+//one connected tree of joints flattened into arrays, everything is indexed the same way and ordered
+//root to leaf, so a parent always comes before its children and the backward reach can just go in
+//index order. The topology is only rebuilt when joints, their relationships, effectors, poles or bases
+//are added or removed, the rest is gathered into it every frame.
+#[derive(Clone, Debug)]
+pub struct JointChain{
+    pub root: Entity,
+    pub entities: Vec<Entity>,
+    pub effector_entities: Vec<Vec<Entity>>,
+    pub pole_entities: Vec<Option<Entity>>,
+    pub base_entities: Vec<Option<Entity>>,
+
+    //the blend weight between the input pose and the solved pose for each joint
+    pub weights: Vec<f32>,
+    //the Transform each joint was last written with, a joint whose Transform doesn't match has a new input pose
+    pub written_transforms: Vec<Option<Transform>>,
+    //the global transform of each joint's ChildOf parent when it was written, so a parent moved by animation
+    //gives an unanimated child a new input pose too
+    pub written_parents: Vec<Option<GlobalTransform>>,
+    pub settings: IkChainSettings,
+    //set while gathering if something the chain points at is missing, invalid chains aren't solved or written back
+    pub invalid: Option<IkError>,
+
+    //what actually gets solved, indexed the same way as entities
+    pub fabrik: FabrikChain,
+}
+
+impl JointChain{
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn build(
+        root: Entity,
+        children_q: &Query<&JointChildren>,
+        links_q: &Query<(Option<&JointEffectors>, Option<&EEJoint>, Option<&PoleJoint>, Option<&BaseJoint>)>,
+        effectors_q: &Query<&EndEffector>,
+    ) -> Self{
+        let mut entities = vec![root];
+        let mut parents = vec![None];
+        let mut seen = EntityHashSet::from_iter([root]);
+
+        //breadth first, which keeps parents in front of their children
+        let mut i = 0;
+        while i < entities.len() {
+            if let Ok(children) = children_q.get(entities[i]) {
+                for child in children.0.iter() {
+                    if seen.insert(*child) {
+                        entities.push(*child);
+                        parents.push(Some(i));
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let len = entities.len();
+        let mut fabrik = FabrikChain::new();
+        for parent in parents {
+            //parents always come first when walking breadth first
+            fabrik.add_joint(Joint::default(), JointTransform::IDENTITY, parent);
+        }
+
+        let links: Vec<_> = entities.iter().map(|entity| links_q.get(*entity).unwrap_or_default()).collect();
+        let effector_entities: Vec<Vec<Entity>> = links.iter().map(|(effectors, ee_joint, _, _)| {
+            let mut effector_entities = effectors.map_or(Vec::new(), |effectors| effectors.0.clone());
+            //an ee joint that never got its end effector attached (e.g. it was despawned) still ends up here, so gathering flags it
+            if let Some(ee_joint) = ee_joint && !effector_entities.contains(&ee_joint.0) {
+                effector_entities.push(ee_joint.0);
+            }
+            effector_entities
+        }).collect();
+        let pole_entities = links.iter().map(|(_, _, pole_joint, _)| pole_joint.map(|pole_joint| pole_joint.0)).collect();
+        let base_entities: Vec<Option<Entity>> = links.iter().map(|(_, _, _, base_joint)| base_joint.map(|base_joint| base_joint.0)).collect();
+
+        //a missing EndEffector makes the chain invalid anyway, bases above the root pull on everything below them
+        fabrik.update_forward_order(|i| {
+            let anchored = (i > 0 && base_entities[i].is_some()).then_some(None);
+            effector_entities[i].iter().map(|effector| effectors_q.get(*effector).ok().and_then(|ee| ee.chain_length)).chain(anchored)
+        });
+
+        Self{
+            root,
+            entities,
+            effector_entities,
+            pole_entities,
+            base_entities,
+            weights: vec![1.0; len],
+            written_transforms: vec![None; len],
+            written_parents: vec![None; len],
+            settings: IkChainSettings::default(),
+            invalid: None,
+            fabrik,
+        }
+    }
+
+    pub fn len(&self) -> usize{
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.entities.is_empty()
+    }
+}
+
+//The solver's side of a chain, plain arrays with no entities or World, so the same solver can also be used
+//on its own (tools, servers, tests). Joints are added root to leaf with add_joint, then solve takes the targets.
+#[derive(Clone, Debug, Default)]
+pub struct FabrikChain{
+    //every array has one entry per joint. Only add_joint grows them and the setters only write to joints
+    //that exist, so they can't get out of step with each other
+    pub(crate) parents: Vec<Option<usize>>,
+    pub(crate) children: Vec<Vec<usize>>,
+    //leaf to root, only the joints that have an end effector on or above them (within its chain_length),
+    //which are the only ones the forward reach moves
+    pub(crate) forward_order: Vec<usize>,
+
+    pub(crate) joints: Vec<Joint>,
+    //global transforms, the solved pose ends up here
+    pub(crate) transforms: Vec<JointTransform>,
+    //the last pose each joint was given from outside the solver (animation, or whatever spawned it). The IkWeight blend
+    //and the aim and pole weights are measured from it, so they don't compound when solving from the previous pose
+    pub(crate) input_transforms: Vec<JointTransform>,
+    pub(crate) constraints: Vec<Option<RotationConstraint>>,
+    pub(crate) hinges: Vec<Option<HingeConstraint>>,
+    pub(crate) locks: Vec<JointLock>,
+    //every end effector on each joint
+    pub(crate) effectors: Vec<Vec<(EndEffector, JointTransform)>>,
+    pub(crate) poles: Vec<Option<(FabrikPole, JointTransform)>>,
+    //the bottom of a joint with a base is pinned to it, the root's base keeps the chain in place and any others
+    //close it into a loop. A chain without any follows its end effectors freely
+    pub(crate) bases: Vec<Option<(FabrikBase, JointTransform)>>,
+    pub(crate) state: IkChainState,
+    //how close each end effector got after the last solve, in the same order as effectors
+    pub(crate) effector_states: Vec<Vec<IkEffectorState>>,
+    //the length multiplier each joint was solved with, 1.0 unless it can stretch
+    pub(crate) stretches: Vec<f32>,
+
+    //reused every iteration, so solving doesn't allocate
+    pub(crate) scratch_rots: Vec<Quat>,
+    pub(crate) scratch_weights: Vec<f32>,
+    pub(crate) scratch_segment: Vec<usize>,
+    pub(crate) scratch_points: Vec<Vec3>,
+    pub(crate) scratch_reaches: Vec<bool>,
+    pub(crate) scratch_subtree: Vec<usize>,
+    pub(crate) scratch_base_offsets: Vec<Vec3>,
+    pub(crate) scratch_joints: Vec<Joint>,
+    pub(crate) scratch_sized_joints: Vec<Joint>,
+    pub(crate) scratch_stretches: Vec<Option<f32>>,
+}
+
+//what the solver is allowed to change about a joint, see IkPinned and IkLockRotation
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub enum JointLock{
+    #[default]
+    Free,
+    Rotation,
+    Pinned,
+}
+
+//something for a FabrikChain to reach for, by joint index
+#[derive(Clone, Copy, Debug)]
+pub enum FabrikTarget{
+    Effector{ joint: usize, effector: EndEffector, transform: JointTransform },
+    Pole{ joint: usize, pole: FabrikPole, transform: JointTransform },
+}
+
+//a PoleTarget without the entity, the joint is whichever one it's given to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FabrikPole{
+    pub chain_length: Option<usize>,
+    pub weight: f32,
+}
+
+impl Default for FabrikPole{
+    fn default() -> Self {
+        PoleTarget::default().into()
+    }
+}
+
+impl From<PoleTarget> for FabrikPole{
+    fn from(pole: PoleTarget) -> Self {
+        Self{
+            chain_length: pole.chain_length,
+            weight: pole.weight,
+        }
+    }
+}
+
+//a Base without the entity, see Base for the radius and weight
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FabrikBase{
+    pub radius: f32,
+    pub weight: f32,
+}
+
+impl Default for FabrikBase{
+    fn default() -> Self {
+        Base::default().into()
+    }
+}
+
+impl From<Base> for FabrikBase{
+    fn from(base: Base) -> Self {
+        Self{
+            radius: base.radius,
+            weight: base.weight,
+        }
+    }
+}
+
+impl FabrikChain{
+    pub fn new() -> Self{
+        Self{
+            state: IkChainState{ error: f32::INFINITY, ..default() },
+            ..default()
+        }
+    }
+
+    //adds a joint at the given global transform and returns its index, a parent has to be added before its children.
+    //None if the parent hasn't been added yet
+    pub fn add_joint(&mut self, joint: Joint, transform: JointTransform, parent: Option<usize>) -> Option<usize>{
+        let index = self.joints.len();
+        if parent.is_some_and(|parent| parent >= index) {
+            return None;
+        }
+        if let Some(parent) = parent {
+            self.children[parent].push(index);
+        }
+        self.parents.push(parent);
+        self.children.push(Vec::new());
+        self.joints.push(joint);
+        self.transforms.push(transform);
+        self.input_transforms.push(transform);
+        self.constraints.push(None);
+        self.hinges.push(None);
+        self.locks.push(JointLock::Free);
+        self.effectors.push(Vec::new());
+        self.poles.push(None);
+        self.bases.push(None);
+        self.effector_states.push(Vec::new());
+        self.stretches.push(1.0);
+        Some(index)
+    }
+
+    pub fn len(&self) -> usize{
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.joints.is_empty()
+    }
+
+    pub fn parent(&self, index: usize) -> Option<usize>{
+        self.parents.get(index).copied().flatten()
+    }
+
+    pub fn children(&self, index: usize) -> &[usize]{
+        self.children.get(index).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn joints(&self) -> &[Joint]{
+        &self.joints
+    }
+
+    pub fn transforms(&self) -> &[JointTransform]{
+        &self.transforms
+    }
+
+    pub fn constraints(&self) -> &[Option<RotationConstraint>]{
+        &self.constraints
+    }
+
+    pub fn hinges(&self) -> &[Option<HingeConstraint>]{
+        &self.hinges
+    }
+
+    pub fn locks(&self) -> &[JointLock]{
+        &self.locks
+    }
+
+    pub fn effectors(&self) -> &[Vec<(EndEffector, JointTransform)>]{
+        &self.effectors
+    }
+
+    pub fn poles(&self) -> &[Option<(FabrikPole, JointTransform)>]{
+        &self.poles
+    }
+
+    pub fn bases(&self) -> &[Option<(FabrikBase, JointTransform)>]{
+        &self.bases
+    }
+
+    pub fn state(&self) -> IkChainState{
+        self.state
+    }
+
+    pub fn effector_states(&self) -> &[Vec<IkEffectorState>]{
+        &self.effector_states
+    }
+
+    pub fn stretches(&self) -> &[f32]{
+        &self.stretches
+    }
+
+    //the setters below are ignored if the joint doesn't exist
+    pub fn set_joint(&mut self, index: usize, joint: Joint){
+        if let Some(slot) = self.joints.get_mut(index) {
+            *slot = joint;
+        }
+    }
+
+    //moves the joint to a new global transform, which is also the pose the weights are measured from
+    pub fn set_transform(&mut self, index: usize, transform: JointTransform){
+        if let (Some(slot), Some(input)) = (self.transforms.get_mut(index), self.input_transforms.get_mut(index)) {
+            *slot = transform;
+            *input = transform;
+        }
+    }
+
+    pub fn set_constraint(&mut self, index: usize, constraint: Option<RotationConstraint>){
+        if let Some(slot) = self.constraints.get_mut(index) {
+            *slot = constraint;
+        }
+    }
+
+    pub fn set_hinge(&mut self, index: usize, hinge: Option<HingeConstraint>){
+        if let Some(slot) = self.hinges.get_mut(index) {
+            *slot = hinge;
+        }
+    }
+
+    pub fn set_lock(&mut self, index: usize, lock: JointLock){
+        if let Some(slot) = self.locks.get_mut(index) {
+            *slot = lock;
+        }
+    }
+
+    //pins the joint's bottom to a base at the given global transform, radius and weight let it give way like Base does
+    pub fn set_base(&mut self, index: usize, transform: JointTransform, radius: f32, weight: f32){
+        let Some(slot) = self.bases.get_mut(index) else { return };
+        *slot = Some((FabrikBase{ radius, weight }, transform));
+        self.refresh_forward_order();
+    }
+
+    pub fn remove_base(&mut self, index: usize){
+        let Some(slot) = self.bases.get_mut(index) else { return };
+        *slot = None;
+        self.refresh_forward_order();
+    }
+
+    //swaps the current targets for new ones, targets on joints that don't exist are ignored
+    pub fn set_targets(&mut self, targets: &[FabrikTarget]){
+        self.effectors.iter_mut().for_each(Vec::clear);
+        self.poles.fill(None);
+        for target in targets {
+            match *target {
+                FabrikTarget::Effector{ joint, effector, transform } => if let Some(effectors) = self.effectors.get_mut(joint) {
+                    effectors.push((effector, transform));
+                },
+                FabrikTarget::Pole{ joint, pole, transform } => if let Some(slot) = self.poles.get_mut(joint) {
+                    *slot = Some((pole, transform));
+                },
+            }
+        }
+
+        self.refresh_forward_order();
+    }
+
+    //works the forward order out again from the current end effectors and bases
+    pub(crate) fn refresh_forward_order(&mut self){
+        let Self{ parents, effectors, bases, forward_order, scratch_reaches, .. } = self;
+        fill_forward_order(parents, |i| {
+            let anchored = (parents[i].is_some() && bases[i].is_some()).then_some(None);
+            effectors[i].iter().map(|(ee, _)| ee.chain_length).chain(anchored)
+        }, scratch_reaches, forward_order);
+    }
+
+    //chain_lengths gives the chain_length of every end effector on a joint
+    pub(crate) fn update_forward_order<I: IntoIterator<Item = Option<usize>>>(&mut self, chain_lengths: impl Fn(usize) -> I){
+        fill_forward_order(&self.parents, chain_lengths, &mut self.scratch_reaches, &mut self.forward_order);
+    }
+}
+
+fn fill_forward_order<I: IntoIterator<Item = Option<usize>>>(parents: &[Option<usize>], chain_lengths: impl Fn(usize) -> I, reaches_effector: &mut Vec<bool>, forward_order: &mut Vec<usize>){
+    let len = parents.len();
+    reaches_effector.clear();
+    reaches_effector.resize(len, false);
+
+    //walks down from every end effector's joint, as far as its chain length goes
+    for i in 0..len {
+        for chain_length in chain_lengths(i) {
+            let mut current = Some(i);
+            let mut count = 0;
+            while let Some(joint) = current && chain_length.is_none_or(|chain_length| count < chain_length.max(1)) {
+                reaches_effector[joint] = true;
+                current = parents[joint];
+                count += 1;
+            }
+        }
+    }
+    forward_order.clear();
+    forward_order.extend((0..len).rev().filter(|i| reaches_effector[*i]));
+}
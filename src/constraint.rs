@@ -1,56 +1,108 @@
 use bevy::prelude::*;
 
-pub fn constrain_direction_cone(
+use crate::utils::quat_abs;
+
+//swing limits, the x limits (min, max) are rotations around the parent's x axis (towards -z / +z)
+//and the z limits are rotations around the parent's z axis (towards +x / -x), each quadrant
+//of the cone is an ellipse between its two limits
+pub fn constrain_direction_ellipse(
     main_direction: Vec3,
     parent_direction: Vec3,
-    max_angle: f32,
+    parent_right: Vec3,
+    x_limits: Vec2,
+    z_limits: Vec2,
     strength: f32,
 ) -> Vec3{
-    let cos_theta = main_direction.dot(parent_direction);
-    let cos_max = max_angle.cos();
-    if cos_theta >= cos_max {
-        return main_direction;
-    }
 
-    let ortho = (main_direction - parent_direction * cos_theta).try_normalize().unwrap_or_else(||{
+    let swing_x = parent_right.reject_from_normalized(parent_direction).try_normalize().unwrap_or_else(||{
         parent_direction.any_orthonormal_vector()
     });
+    let swing_z = swing_x.cross(parent_direction).normalize();
 
-    let constrained = (parent_direction * cos_max + ortho * max_angle.sin()).normalize();
+    let x = main_direction.dot(swing_x);
+    let z = main_direction.dot(swing_z);
+    let y = main_direction.dot(parent_direction);
 
-    main_direction.slerp(constrained, strength).normalize()
-}
+    let flat_length = (x * x + z * z).sqrt();
+    let swing_angle = flat_length.atan2(y);
 
-pub fn constrain_direction_ellipse(
-    main_direction: Vec3,
-    parent_direction: Vec3,
-    max_x: f32,
-    max_z: f32,
-    strength: f32,
-) -> Vec3{
+    let (cos_phi, sin_phi) = if flat_length > 0.000001 {
+        (x / flat_length, z / flat_length)
+    } else {
+        (1.0, 0.0)
+    };
 
-    let swing_x = parent_direction.any_orthonormal_vector();
-    let swing_z = parent_direction.cross(swing_x).normalize();
+    //a positive rotation around z swings towards -x
+    let limit_x = f32::max(if cos_phi >= 0.0 { -z_limits.x } else { z_limits.y }, 0.0000001);
+    let limit_z = f32::max(if sin_phi >= 0.0 { x_limits.y } else { -x_limits.x }, 0.0000001);
 
-    let x = main_direction.dot(swing_x);
-    let z = main_direction.dot(swing_z);
+    let max_angle = 1.0 / ((cos_phi / limit_x).powi(2) + (sin_phi / limit_z).powi(2)).sqrt();
+
+    if swing_angle <= max_angle {
+        return main_direction;
+    }
 
-    let sx = max_x.sin();
-    let sz = max_z.sin();
-    
-    let ellipse_value = (x * x) / (sx * sx) + (z * z) / (sz * sz);
+    let constrained = parent_direction * max_angle.cos() + (swing_x * cos_phi + swing_z * sin_phi) * max_angle.sin();
 
-    let (cx, cz) = if ellipse_value <= 1.0 {
-        (x, z)
+    main_direction.slerp(constrained.normalize(), strength).normalize()
+}
+
+//splits a rotation into rotation = swing * twist, where the twist is only around the given axis
+pub fn swing_twist_decomposition(
+    rotation: Quat,
+    twist_axis: Vec3,
+) -> (Quat, Quat){
+    let projected = twist_axis * Vec3::new(rotation.x, rotation.y, rotation.z).dot(twist_axis);
+
+    let twist = Quat::from_xyzw(projected.x, projected.y, projected.z, rotation.w);
+
+    //a 180 degree swing has no defined twist
+    let twist = if twist.length_squared() > 0.0000001 {
+        twist.normalize()
     } else {
-        let scale = ellipse_value.sqrt();
-        (x / scale, z / scale)
+        Quat::IDENTITY
     };
+    let swing = rotation * twist.inverse();
 
-    let cy = (1.0 - cx * cx - cz * cz).max(0.0).sqrt();
+    (swing, twist)
+}
 
-    let constrained = swing_x * cx + swing_z * cz + parent_direction * cy;
+//takes a rotation relative to its rest pose and returns how much it needs to be twisted around the
+//bone's axis to bring its twist within min and max
+pub fn twist_correction(
+    relative_rotation: Quat,
+    bone_axis: Vec3,
+    min_angle: f32,
+    max_angle: f32,
+    strength: f32,
+) -> f32{
+    let bone_axis = bone_axis.normalize();
+    let (_, twist) = swing_twist_decomposition(relative_rotation, bone_axis);
+    let twist = quat_abs(twist);
 
-    main_direction.slerp(constrained.normalize(), strength).normalize()
+    let angle = 2.0 * Vec3::new(twist.x, twist.y, twist.z).dot(bone_axis).atan2(twist.w);
+    let clamped = angle.clamp(min_angle, max_angle);
+
+    (clamped - angle) * strength
 }
 
+pub fn constrain_direction_hinge(
+    main_direction: Vec3,
+    parent_direction: Vec3,
+    hinge_axis: Vec3,
+    min_angle: f32,
+    max_angle: f32,
+    strength: f32,
+) -> Vec3{
+    let reference = parent_direction.reject_from_normalized(hinge_axis).try_normalize().unwrap_or_else(||{
+        hinge_axis.any_orthonormal_vector()
+    });
+
+    //always projects fully onto the hinge plane, strength only softens how far the angle is pulled back within it
+    let projected = main_direction.reject_from_normalized(hinge_axis).try_normalize().unwrap_or(reference);
+
+    let angle = reference.cross(projected).dot(hinge_axis).atan2(reference.dot(projected));
+    let angle = angle + (angle.clamp(min_angle, max_angle) - angle) * strength;
+
+    Quat::from_axis_angle(hinge_axis, angle) * reference
+}
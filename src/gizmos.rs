@@ -2,7 +2,7 @@ use bevy::{color::palettes::css::{BLUE, GREEN, LIGHT_GRAY, RED, WHITE}, prelude:
 
 use crate::JointParent;
 
-use super::{Joint, JointTransform, RotationConstraint};
+use super::{HingeConstraint, Joint, JointTransform, RotationConstraint};
 
 // We can create our own gizmo config group!
 #[derive(Default, GizmoConfigGroup, Reflect)]
@@ -14,7 +14,7 @@ impl Plugin for IkGizmosPlugin{
     fn build(&self, app: &mut App) {
 
         app.add_systems(Startup, gizmo_config_setup);
-        app.add_systems(PostUpdate, (joint_directional_gizmos, rotation_constraint_gizmos).after(TransformSystems::Propagate));
+        app.add_systems(PostUpdate, (joint_directional_gizmos, rotation_constraint_gizmos, hinge_constraint_gizmos).after(TransformSystems::Propagate));
         app.init_resource::<IkGizmoSettings>();
         app.init_gizmo_group::<IkGizmos>();
     }
@@ -66,9 +66,9 @@ pub fn joint_directional_gizmos(
     if settings.directional_gizmos_toggle {
         for (main_j, main_jt) in j_q.iter(){
             let translation = main_jt.translation - (main_jt.rotation * main_j.visual_offset);
-            let main_up = main_jt.local_y();
-            let main_forward = main_jt.local_z();
-            let main_right = main_jt.local_x();
+            let main_up = main_j.up(main_jt.rotation);
+            let main_forward = main_j.forward(main_jt.rotation);
+            let main_right = main_j.right(main_jt.rotation);
             gizmos.arrow(translation, translation + (main_up * main_j.length), settings.up_direction_color);
             gizmos.arrow(translation - (main_forward * main_j.length * 0.2) + (main_up * main_j.length * 0.2), translation + (main_forward * main_j.length * 0.2) + (main_up * main_j.length * 0.2), settings.forward_direction_color);
             gizmos.arrow(translation - (main_right * main_j.length * 0.2) + (main_up * main_j.length * 0.2), translation + (main_right * main_j.length * 0.2) + (main_up * main_j.length * 0.2), settings.right_direction_color);
@@ -85,7 +85,7 @@ pub fn rotation_constraint_gizmos(
 ){
     if settings.rotational_constraint_toggle {
         for (main, constraint, parent) in constraint_q.iter(){
-            if let Ok((main_joint, main_t)) = joint_q.get(main) && let Ok((_, parent_t)) = joint_q.get(parent.0){
+            if let Ok((main_joint, main_t)) = joint_q.get(main) && let Ok((parent_joint, parent_t)) = joint_q.get(parent.0){
                 //first swing constraint arrows in white
             
 
@@ -93,13 +93,15 @@ pub fn rotation_constraint_gizmos(
 
             
                 //identity (centre direction)    
-                let identity_up_dir = constraint.identity * parent_t.local_y().as_vec3();
+                let identity_up_dir = constraint.identity * parent_joint.up(parent_t.rotation);
+                let parent_right = parent_joint.right(parent_t.rotation);
+                let parent_forward = parent_joint.forward(parent_t.rotation);
                 gizmos.arrow(translation, translation + (identity_up_dir * main_joint.length * 0.333), settings.identity_color);
 
             
                 //x swing limits
-                let limit_pos_x = Quat::from_rotation_x(constraint.x.y) * identity_up_dir;
-                let limit_neg_x = Quat::from_rotation_x(-constraint.x.y) * identity_up_dir;
+                let limit_pos_x = Quat::from_axis_angle(parent_right, constraint.x.y) * identity_up_dir;
+                let limit_neg_x = Quat::from_axis_angle(parent_right, constraint.x.x) * identity_up_dir;
 
                 gizmos.arrow(translation, translation + (limit_pos_x * main_joint.length * 0.25), settings.limits_color);
                 gizmos.arrow(translation, translation + (limit_neg_x * main_joint.length * 0.25), settings.limits_color);
@@ -107,18 +109,20 @@ pub fn rotation_constraint_gizmos(
 
             
                 //z swing limits
-                let limit_pos_z = Quat::from_rotation_z(constraint.z.y) * identity_up_dir;
-                let limit_neg_z = Quat::from_rotation_z(-constraint.z.y) * identity_up_dir;
+                let limit_pos_z = Quat::from_axis_angle(parent_forward, constraint.z.y) * identity_up_dir;
+                let limit_neg_z = Quat::from_axis_angle(parent_forward, constraint.z.x) * identity_up_dir;
 
                 gizmos.arrow(translation, translation + (limit_pos_z * main_joint.length * 0.25), settings.limits_color);
                 gizmos.arrow(translation, translation + (limit_neg_z * main_joint.length * 0.25), settings.limits_color);
 
 
                 //twist limits
-                let rot = Transform::IDENTITY.aligned_by(Vec3::Y, main_t.local_y(), Vec3::Z, constraint.identity * parent_t.local_z()).rotation;
-                let iso = Isometry3d::new(translation + (main_t.local_y() * main_joint.length * 0.2), rot);
+                let main_up = main_joint.up(main_t.rotation);
+                let twist_start = Quat::from_axis_angle(main_up, constraint.y.x) * (constraint.identity * parent_forward);
+                let rot = Transform::IDENTITY.aligned_by(Vec3::Y, main_up, Vec3::X, twist_start).rotation;
+                let iso = Isometry3d::new(translation + (main_up * main_joint.length * 0.2), rot);
 
-                gizmos.arc_3d(constraint.x.y * 2.0, main_joint.length * 0.15, iso, settings.limits_color);
+                gizmos.arc_3d(constraint.y.y - constraint.y.x, main_joint.length * 0.15, iso, settings.limits_color);
 
             
             }
@@ -126,3 +130,31 @@ pub fn rotation_constraint_gizmos(
     }
        
 }
+
+pub fn hinge_constraint_gizmos(
+    hinge_q: Query<(Entity, &HingeConstraint, &JointParent)>,
+    joint_q: Query<(&Joint, &JointTransform)>,
+    mut gizmos: Gizmos<IkGizmos>,
+    settings: Res<IkGizmoSettings>,
+){
+    if settings.rotational_constraint_toggle {
+        for (main, hinge, parent) in hinge_q.iter(){
+            if let Ok((main_joint, main_t)) = joint_q.get(main) && let Ok((_, parent_t)) = joint_q.get(parent.0){
+                let translation = main_t.translation - (main_t.rotation * main_joint.visual_offset);
+
+                let rest_frame = parent_t.rotation * hinge.identity.normalize();
+                let hinge_axis = (rest_frame * hinge.axis).normalize();
+
+                //identity (centre direction)
+                let identity_up_dir = rest_frame * main_joint.bone_axis;
+                gizmos.arrow(translation, translation + (identity_up_dir * main_joint.length * 0.333), settings.identity_color);
+
+                //the arc the joint is allowed to swing through, from min to max
+                let start_dir = Quat::from_axis_angle(hinge_axis, hinge.min) * identity_up_dir;
+                let rot = Transform::IDENTITY.aligned_by(Vec3::Y, hinge_axis, Vec3::X, start_dir).rotation;
+
+                gizmos.arc_3d(hinge.max - hinge.min, main_joint.length * 0.25, Isometry3d::new(translation, rot), settings.limits_color);
+            }
+        }
+    }
+}
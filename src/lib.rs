@@ -1,8 +1,9 @@
 use bevy::{
+    app::AnimationSystems,
+    ecs::entity::EntityHashMap,
     prelude::*,
-    platform::collections::HashMap,
 };
-use std::sync::{Arc, Mutex, RwLock};
+use std::fmt;
 
 
 
@@ -14,37 +15,70 @@ mod bookkeeper;
 
 mod utils;
 
+mod setup;
+
+mod chain;
+
+pub use setup::SetupIkChain;
+
+pub use chain::{FabrikBase, FabrikChain, FabrikPole, FabrikTarget, JointChain, JointLock};
+
 pub mod gizmos;
 
+pub mod validation;
+
 
 pub struct IkSolverPlugin;
 
 impl Plugin for IkSolverPlugin{
     fn build(&self, app: &mut App) {
         app.add_systems(PreStartup, bookkeeper::joint_hooks);
+
+        //runs after animation so the animated pose is what the solver starts from
+        app.configure_sets(PostUpdate, (
+            IkSystems::Collect,
+            IkSystems::Solve,
+            IkSystems::Sync,
+        ).chain().after(AnimationSystems).before(TransformSystems::Propagate));
         
         app.add_systems(PostUpdate, (
-            bookkeeper::collect_joint_transforms,
-            bookkeeper::bookkeep_joints_start,
-            solver::solve,
-            bookkeeper::sync_transforms,
-        ).chain().before(TransformSystems::Propagate));
+            (
+                bookkeeper::collect_joint_transforms,
+                bookkeeper::bookkeep_joints_start,
+                bookkeeper::build_chains,
+                bookkeeper::gather_chains,
+                bookkeeper::validate_chains,
+            ).chain().in_set(IkSystems::Collect),
+            solver::solve.in_set(IkSystems::Solve),
+            bookkeeper::sync_transforms.in_set(IkSystems::Sync),
+        ));
         app.add_systems(PostUpdate, (bookkeeper::force_gt).after(TransformSystems::Propagate));
         
         app.insert_resource(IkGlobalSettings::default());
-        app.insert_resource(JointBookkeeping::default());
+        app.init_resource::<JointBookkeeping>();
 
         
     }
 }
 
+//the stages of the ik pipeline, all in PostUpdate after AnimationSystems and before transform propagation
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IkSystems{
+    Collect,
+    Solve,
+    Sync,
+}
+
 #[derive(Resource, Clone, Copy, Debug)]
 #[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
 #[cfg_attr(feature = "bevy_reflect", reflect(Resource))]
 pub struct IkGlobalSettings{
     pub iterations: usize,
     pub minimum_tolerance: f32,
+    //how close an end effector's joint has to get to count as having reached it, for IkTargetReached
+    pub reach_tolerance: f32,
     pub force_global_transform: bool,
+    pub seed_pose: IkSeedPose,
 }
 
 
@@ -53,19 +87,206 @@ impl Default for IkGlobalSettings{
         Self{
             iterations: 10,
             minimum_tolerance: 0.00001,
+            reach_tolerance: 0.001,
             force_global_transform: false,
+            seed_pose: IkSeedPose::Previous,
+        }
+    }
+}
+
+//what pose the solver starts from each frame
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub enum IkSeedPose{
+    //last frame's solved pose, only joints whose Transform changed are read again
+    #[default]
+    Previous,
+    //every joint is read from its Transform hierarchy each frame, so ik acts as a correction on top of
+    //the animated pose (e.g. from an AnimationPlayer). Joints need to be written by animation every frame
+    //for this to be useful, otherwise they just start from last frame's result anyway.
+    Input,
+}
+
+
+//overrides the global settings for a single chain, goes on a BaseJoint or its Base. With several bases in one tree the
+//root joint and its base come first, then the other base joints and their bases from the root up.
+//Anything left at None is read from IkGlobalSettings when the chain is solved
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkChainSettings{
+    pub enabled: bool,
+    pub iterations: Option<usize>,
+    pub minimum_tolerance: Option<f32>,
+    pub reach_tolerance: Option<f32>,
+}
+
+impl Default for IkChainSettings{
+    fn default() -> Self {
+        Self{
+            enabled: true,
+            iterations: None,
+            minimum_tolerance: None,
+            reach_tolerance: None,
         }
     }
 }
 
+//blends between the input pose and the solved pose, goes on a Base or a joint and affects every joint above it
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkWeight(pub f32);
+
+impl Default for IkWeight{
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
 
+//same as an IkWeight of 0.0, but chains with this on their Base or BaseJoint are also not solved at all
 #[derive(Component, Clone, Copy, Default, Debug)]
 #[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkDisabled;
+
+//keeps a joint at its input pose while the rest of the chain is solved, the joints above it hang off it like off a base
+#[derive(Component, Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkPinned;
+
+//keeps a joint's input rotation while solving, it still moves with the joints around it
+#[derive(Component, Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkLockRotation;
+
+//written to the root joint of every chain after solving, error is the distance of the furthest end effector from its joint
+//and converged is true if the chain stopped early because the error got small enough. stalled is true if it stopped early
+//because the error stopped changing before getting there
+#[derive(Component, Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkChainState{
+    pub error: f32,
+    pub iterations_used: usize,
+    pub converged: bool,
+    pub stalled: bool,
+}
+
+//written to every end effector of a solved chain
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkEffectorState{
+    //distance between the end effector and the point of its joint that reaches for it
+    pub error: f32,
+    //error is within the chain's reach_tolerance
+    pub reached: bool,
+    //false if the end effector is further from the base than all the joints between them laid end to end
+    pub reachable: bool,
+}
+
+//triggered on an end effector when its joint gets to it
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct IkTargetReached{
+    pub entity: Entity,
+    pub joint: Entity,
+}
+
+//triggered on an end effector when it moves out of its chain's reach
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct IkTargetUnreachable{
+    pub entity: Entity,
+    pub joint: Entity,
+}
+
+//triggered on the root joint when its chain converges after not having converged the frame before
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct IkChainConverged{
+    pub entity: Entity,
+    pub error: f32,
+    pub iterations_used: usize,
+}
+
+//triggered on the root joint when its chain stops getting any closer without converging, after not having stalled the frame before
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct IkChainStalled{
+    pub entity: Entity,
+    pub error: f32,
+    pub iterations_used: usize,
+}
+
+//why a chain can't be solved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub enum IkError{
+    //an entity in the chain's hierarchy has no Joint
+    MissingJoint(Entity),
+    //the joint's BaseJoint points at an entity that isn't a Base
+    MissingBase{ joint: Entity, base: Entity },
+    //an entity attached to the joint as an end effector isn't an EndEffector
+    MissingEndEffector{ joint: Entity, effector: Entity },
+    //the joint's PoleJoint points at an entity that isn't a PoleTarget
+    MissingPoleTarget{ joint: Entity, pole: Entity },
+}
+
+impl fmt::Display for IkError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IkError::MissingJoint(entity) => write!(f, "{entity} is part of a joint chain but has no Joint"),
+            IkError::MissingBase{ joint, base } => write!(f, "the BaseJoint on {joint} points at {base}, which is not a Base"),
+            IkError::MissingEndEffector{ joint, effector } => write!(f, "{effector} is attached to {joint} as an end effector, but is not an EndEffector"),
+            IkError::MissingPoleTarget{ joint, pole } => write!(f, "the PoleJoint on {joint} points at {pole}, which is not a PoleTarget"),
+        }
+    }
+}
+
+impl std::error::Error for IkError{}
+
+//put on the root joint of a chain that can't be solved, the chain is skipped until the problem is fixed
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct IkInvalid{
+    pub reason: IkError,
+}
+
+//triggered on the root joint when its chain becomes invalid
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct IkChainInvalid{
+    pub entity: Entity,
+    pub reason: IkError,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
 #[require(Transform, JointTransform)]
 pub struct Joint{
+    //length, visual_offset and anchor_offset are in the joint's local space, and get scaled by its global scale
+    //(anchor_offset by its parent's, or its base's for a root joint)
     pub length: f32,
     pub visual_offset: Vec3,
     pub anchor_offset: Vec3,
+    //the local axis the joint points along, Y by default but imported skeletons often use X or -Z
+    pub bone_axis: Vec3,
+    //the local axis used to orient the joint around its bone axis, should be perpendicular to the bone axis
+    pub secondary_axis: Vec3,
+    //how far the joint can squash or stretch towards an end effector, as multipliers of its length,
+    //both at 1.0 keeps it rigid. It only stretches for end effectors out of reach and only squashes for ones
+    //closer than the chain can fold. The stretch is written into the scale along the bone axis
+    pub min_stretch: f32,
+    pub max_stretch: f32,
+    //0.0 stretches as far as it takes to reach the end effector (within the limits), 1.0 doesn't stretch at all
+    pub stretch_stiffness: f32,
+}
+
+impl Default for Joint{
+    fn default() -> Self {
+        Self{
+            length: 0.0,
+            visual_offset: Vec3::ZERO,
+            anchor_offset: Vec3::ZERO,
+            bone_axis: Vec3::Y,
+            secondary_axis: Vec3::Z,
+            min_stretch: 1.0,
+            max_stretch: 1.0,
+            stretch_stiffness: 0.0,
+        }
+    }
 }
 
 #[derive(Component, Debug, PartialEq, Eq)]
@@ -98,42 +319,153 @@ impl JointTransform {
         rotation: Quat::IDENTITY,
         translation: Vec3::ZERO,
     };
+
+    pub fn scale(&self) -> Vec3{
+        self.scale
+    }
+
+    pub fn rotation(&self) -> Quat{
+        self.rotation
+    }
+
+    pub fn translation(&self) -> Vec3{
+        self.translation
+    }
+}
+
+//mostly for FabrikChain, in the ecs the plugin keeps joint transforms up to date itself
+impl From<Transform> for JointTransform{
+    fn from(transform: Transform) -> Self {
+        Self{
+            scale: transform.scale,
+            rotation: transform.rotation,
+            translation: transform.translation,
+        }
+    }
+}
+
+impl From<JointTransform> for Transform{
+    fn from(jt: JointTransform) -> Self {
+        Transform::from_scale(jt.scale).with_rotation(jt.rotation).with_translation(jt.translation)
+    }
 }
 
 #[derive(Component, Clone, Copy, Debug)]
 #[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
 #[require(Transform, JointTransform)]
 pub struct EndEffector{
-    pub joint: Option<Entity>,
     pub joint_center: bool,
-    pub joint_copy_rotation: bool,
-    pub weight: f32,
+    //how strongly the joint is pulled onto the end effector's position, 0..1
+    pub position_weight: f32,
+    //how much of the end effector's rotation the joint takes on, 0..1, applied after the position so 1 copies it exactly
+    pub rotation_weight: f32,
+    pub mode: EffectorMode,
+    //how many joints the end effector moves, counting down from its own joint, None goes all the way to the root
+    pub chain_length: Option<usize>,
+}
+
+//what a joint does with its end effector
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub enum EffectorMode{
+    //the joint's top (or center) is pulled onto the end effector
+    #[default]
+    Reach,
+    //the joint turns the given local axis towards the end effector without moving onto it, the joints below it up to
+    //the nearest branch (or chain_length) share the turn (heads, eyes, turrets). position_weight is how far it turns from the
+    //input pose, rotation_weight is unused
+    Aim(Vec3),
 }
 
 impl Default for EndEffector{
     fn default() -> Self {
         Self {
-            joint: None,
             joint_center: false,
-            joint_copy_rotation: false,
-            weight: 1.0
+            position_weight: 1.0,
+            rotation_weight: 0.0,
+            mode: EffectorMode::Reach,
+            chain_length: None,
         }
     }
 }
 
+//attaches an end effector to a joint, a joint can have any number of them and averages them by their weights
+#[derive(Component, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+#[relationship(relationship_target = JointEffectors)]
+#[require(EndEffector)]
+pub struct EffectorJoint(Entity);
+
+impl EffectorJoint{
+    pub fn joint(&self) -> Entity{
+        self.0
+    }
+}
+
+//every end effector attached to the joint
+#[derive(Component, Debug, Default)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+#[relationship_target(relationship = EffectorJoint)]
+#[require(Joint)]
+pub struct JointEffectors(Vec<Entity>);
+
+impl JointEffectors{
+    pub fn effectors(&self) -> &[Entity]{
+        &self.0
+    }
+}
+
+//shortcut for attaching an end effector from the joint's side, the end effector gets an EffectorJoint pointing back here
 #[derive(Component, Clone, Copy, Debug)]
 #[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
 #[require(Joint)]
 pub struct EEJoint(pub Entity);
 
+//bends the joints below its joint towards the plane made by their base, the joint's end effector and the pole
 #[derive(Component, Clone, Copy, Debug)]
 #[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+#[require(Transform, JointTransform)]
+pub struct PoleTarget{
+    pub joint: Option<Entity>,
+    //how many joints down from the pole joint get bent towards the pole, None goes until the base joint or the first branching joint
+    pub chain_length: Option<usize>,
+    pub weight: f32,
+}
+
+impl Default for PoleTarget{
+    fn default() -> Self {
+        Self {
+            joint: None,
+            chain_length: None,
+            weight: 1.0,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+#[require(Joint)]
+pub struct PoleJoint(pub Entity);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
 #[require(JointTransform)]
-pub struct Base(pub Entity);
+pub struct Base{
+    pub joint: Entity,
+    //how far the solver may move the base joint away from the base when its end effectors are out of reach
+    //(e.g. lowering the hips so both feet reach the ground), 0.0 keeps it pinned. The Base entity itself isn't moved
+    pub radius: f32,
+    //how much of the distance the end effectors are out of reach by the base joint makes up for, within the radius
+    pub weight: f32,
+}
 
 impl Default for Base{
     fn default() -> Self {
-        Self(Entity::PLACEHOLDER)
+        Self {
+            joint: Entity::PLACEHOLDER,
+            radius: 0.0,
+            weight: 1.0,
+        }
     }
 }
 
@@ -143,27 +475,14 @@ impl Default for Base{
 pub struct BaseJoint(pub Entity);
 
 
-#[derive(Resource, Clone, Debug)]
+//every connected tree of joints as its own chain, each one is solved on its own thread
+#[derive(Resource, Clone, Debug, Default)]
 pub struct JointBookkeeping{
-    pub joints: Arc<Mutex<HashMap<Entity, (Joint, JointTransform)>>>,
-    pub parents: Arc<RwLock<HashMap<Entity, JointParent>>>,
-    pub children: Arc<RwLock<HashMap<Entity, JointChildren>>>,
-    pub ends: Arc<RwLock<HashMap<Entity, (EndEffector, JointTransform)>>>,
-    pub bases: Arc<RwLock<HashMap<Entity, (Base, JointTransform)>>>,
-    pub last_diff: Vec3,
-}
-
-impl Default for JointBookkeeping{
-    fn default() -> Self {
-        Self{
-            joints: Arc::new(Mutex::new(HashMap::new())),
-            parents: Arc::new(RwLock::new(HashMap::new())),
-            children: Arc::new(RwLock::new(HashMap::new())),
-            ends: Arc::new(RwLock::new(HashMap::new())),
-            bases: Arc::new(RwLock::new(HashMap::new())),
-            last_diff: Vec3::ZERO,
-        }
-    }
+    pub chains: Vec<JointChain>,
+    //set by the component hooks whenever the shape of any chain changes
+    pub(crate) dirty: bool,
+    //ee joints whose target only got its EndEffector from the ee joint's hook, for IkValidationPlugin to report
+    pub(crate) implicit_effectors: EntityHashMap<Entity>,
 }
 
 
@@ -175,6 +494,7 @@ pub struct RotationConstraint{
     pub weight: f32,
     pub strength: f32,
     pub default_dir: Vec3,
+    //(min, max) angles for each axis, y is the twist and x / z are the swing, they don't have to be symmetric
     pub y: Vec2,
     pub x: Vec2,
     pub z: Vec2,    
@@ -193,3 +513,29 @@ impl Default for RotationConstraint {
         }
     }
 }
+
+//restricts a joint to only rotate around one axis, relative to its parent (or base)
+#[derive(Component, Copy, Clone, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub struct HingeConstraint{
+    pub identity: Quat,
+    pub weight: f32,
+    pub strength: f32,
+    //the local axis the joint rotates around, should be perpendicular to the joint's bone axis
+    pub axis: Vec3,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for HingeConstraint {
+    fn default() -> Self {
+        Self{
+            identity: Quat::IDENTITY,
+            weight: 1.0,
+            strength: 0.75,
+            axis: Vec3::X,
+            min: -1.570,
+            max: 1.570,
+        }
+    }
+}
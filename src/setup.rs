@@ -0,0 +1,239 @@
+use bevy::{
+    ecs::entity::EntityHashSet,
+    mesh::skinning::SkinnedMesh,
+    prelude::*,
+};
+
+use super::{
+    BaseJoint,
+    EEJoint,
+    EndEffector,
+    Joint,
+    JointParent,
+};
+
+
+//Turns the bones of an already spawned skeleton (usually a glTF scene) into a joint chain, from the root bone up to the tip bone.
+//Each bone gets a Joint with its length and bone axis measured from the bone hierarchy, a JointParent to the bone below it, the root bone
+//gets a BaseJoint and the tip bone an EEJoint. Queue it once the scene has actually spawned, e.g. in an observer for
+//SceneInstanceReady. Bone hierarchies use ChildOf, so IkGlobalSettings::force_global_transform should be true.
+#[derive(Clone, Debug)]
+pub struct SetupIkChain{
+    //the entity the scene was spawned on, bones are only searched for below this
+    pub scene_root: Entity,
+    pub root_bone: String,
+    pub tip_bone: String,
+    //the Base to pin the root bone to, None spawns one next to the root bone that follows the root bone's parent
+    pub base: Option<Entity>,
+    //the End Effector for the tip bone, None spawns one at the tip bone's current position
+    pub end_effector: Option<Entity>,
+    //the tip bone has no child in the chain to measure against, None uses the distance to its first child bone
+    //if it has one, otherwise the length of the bone below it. In the tip bone's local space, like every joint length
+    pub tip_length: Option<f32>,
+}
+
+impl SetupIkChain{
+    pub fn new(scene_root: Entity, root_bone: impl Into<String>, tip_bone: impl Into<String>) -> Self{
+        Self{
+            scene_root,
+            root_bone: root_bone.into(),
+            tip_bone: tip_bone.into(),
+            base: None,
+            end_effector: None,
+            tip_length: None,
+        }
+    }
+}
+
+impl Command for SetupIkChain{
+    fn apply(self, world: &mut World) {
+        let descendants = descendants(world, self.scene_root);
+
+        //if the scene is skinned only the skin's joints count as bones, so meshes or empties with the same name are ignored
+        let skin_joints: EntityHashSet = descendants.iter()
+            .filter_map(|e| world.get::<SkinnedMesh>(*e))
+            .flat_map(|skin| skin.joints.iter().copied())
+            .collect();
+        let is_bone = |e: &Entity| skin_joints.is_empty() || skin_joints.contains(e);
+        let find_bone = |name: &str| descendants.iter().copied().find(|e| {
+            is_bone(e) && world.get::<Name>(*e).is_some_and(|n| n.as_str() == name)
+        });
+
+        let Some(root_bone) = find_bone(&self.root_bone) else {
+            warn!("SetupIkChain: no bone named {:?} below {}", self.root_bone, self.scene_root);
+            return;
+        };
+        let Some(tip_bone) = find_bone(&self.tip_bone) else {
+            warn!("SetupIkChain: no bone named {:?} below {}", self.tip_bone, self.scene_root);
+            return;
+        };
+
+        //walks down from the tip, then flips it so the chain goes root to tip
+        let mut chain = vec![tip_bone];
+        let mut current = tip_bone;
+        while current != root_bone {
+            let Some(parent) = world.get::<ChildOf>(current).map(|c| c.parent()) else {
+                warn!("SetupIkChain: {:?} is not an ancestor of {:?}", self.root_bone, self.tip_bone);
+                return;
+            };
+            if parent == self.scene_root {
+                warn!("SetupIkChain: {:?} is not an ancestor of {:?}", self.root_bone, self.tip_bone);
+                return;
+            }
+            chain.push(parent);
+            current = parent;
+        }
+        chain.reverse();
+
+        let tip_global = global_transform(world, tip_bone);
+
+        //a bone points towards its child, which sits along the bone's local axis. Lengths are measured in the bone's
+        //local space too, the solver scales them by the bone's global scale
+        let tip_child = world.get::<Children>(tip_bone).and_then(|c| c.iter().find(is_bone));
+        let child_offsets: Vec<Vec3> = chain.iter().skip(1).chain(tip_child.iter())
+            .map(|child| world.get::<Transform>(*child).map(|t| t.translation).unwrap_or_default())
+            .collect();
+
+        let mut lengths: Vec<f32> = child_offsets.iter().take(chain.len() - 1).map(|offset| offset.length()).collect();
+        let tip_length = self.tip_length.unwrap_or_else(|| {
+            match child_offsets.get(chain.len() - 1) {
+                Some(offset) => offset.length(),
+                None => lengths.last().copied().unwrap_or(0.0),
+            }
+        });
+        lengths.push(tip_length);
+
+        let mut bone_axes: Vec<Vec3> = child_offsets.iter().map(|offset| offset.try_normalize().unwrap_or(Vec3::Y)).collect();
+        if bone_axes.len() < chain.len() {
+            bone_axes.push(bone_axes.last().copied().unwrap_or(Vec3::Y));
+        }
+
+        //bones are placed at their head, so there's no visual offset
+        for (i, bone) in chain.iter().enumerate() {
+            let bone_axis = bone_axes[i];
+            let secondary_axis = if bone_axis.z.abs() < 0.9 { Vec3::Z } else { Vec3::Y };
+            let mut bone_e = world.entity_mut(*bone);
+            bone_e.insert(Joint{
+                length: lengths[i],
+                visual_offset: Vec3::ZERO,
+                anchor_offset: Vec3::ZERO,
+                bone_axis,
+                secondary_axis: secondary_axis.reject_from_normalized(bone_axis).normalize(),
+                ..default()
+            });
+            if i > 0 {
+                bone_e.insert(JointParent(chain[i - 1]));
+            }
+        }
+
+        let base = self.base.unwrap_or_else(|| {
+            let local = world.get::<Transform>(root_bone).copied().unwrap_or_default();
+            let parent = world.get::<ChildOf>(root_bone).map(|c| c.parent());
+            let mut base = world.spawn((
+                Name::new(format!("{} IK Base", self.root_bone)),
+                local,
+            ));
+            if let Some(parent) = parent {
+                base.insert(ChildOf(parent));
+            }
+            base.id()
+        });
+        //the BaseJoint hook keeps the radius and weight of a Base that already exists
+        world.entity_mut(root_bone).insert(BaseJoint(base));
+
+        let end_effector = self.end_effector.unwrap_or_else(|| {
+            let tip = tip_global.compute_transform();
+            world.spawn((
+                Name::new(format!("{} IK End", self.tip_bone)),
+                Transform::from_translation(tip.translation + tip.rotation * (tip.scale * bone_axes[bone_axes.len() - 1] * tip_length)).with_rotation(tip.rotation),
+                EndEffector::default(),
+            )).id()
+        });
+        world.entity_mut(tip_bone).insert(EEJoint(end_effector));
+    }
+}
+
+fn descendants(world: &World, root: Entity) -> Vec<Entity>{
+    let mut found = Vec::new();
+    let mut stack = vec![root];
+    while let Some(entity) = stack.pop() {
+        let Some(children) = world.get::<Children>(entity) else { continue };
+        for child in children.iter() {
+            found.push(child);
+            stack.push(child);
+        }
+    }
+    found
+}
+
+//the scene might not have been through transform propagation yet, so this is built up from the local transforms
+fn global_transform(world: &World, entity: Entity) -> GlobalTransform{
+    let mut global = GlobalTransform::from(world.get::<Transform>(entity).copied().unwrap_or_default());
+    let mut current = entity;
+    while let Some(parent) = world.get::<ChildOf>(current).map(|c| c.parent()) {
+        global = GlobalTransform::from(world.get::<Transform>(parent).copied().unwrap_or_default()) * global;
+        current = parent;
+    }
+    global
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const EPSILON: f32 = 0.001;
+
+    fn spawn_bone(world: &mut World, name: &str, translation: Vec3, parent: Entity) -> Entity{
+        world.spawn((Name::new(name.to_string()), Transform::from_translation(translation), ChildOf(parent))).id()
+    }
+
+    //a skinned leg with an attachment point on the ankle that isn't one of the skin's joints, spawned before the toe
+    //so it's the ankle's first child
+    fn spawn_leg(world: &mut World) -> (Entity, [Entity; 4]){
+        let scene_root = world.spawn(Transform::default()).id();
+        let armature = spawn_bone(world, "Armature", Vec3::ZERO, scene_root);
+        let hip = spawn_bone(world, "hip", Vec3::new(0.0, 1.0, 0.0), armature);
+        let knee = spawn_bone(world, "knee", Vec3::new(0.0, 0.5, 0.0), hip);
+        let ankle = spawn_bone(world, "ankle", Vec3::new(0.4, 0.0, 0.0), knee);
+        spawn_bone(world, "ankle_socket", Vec3::new(0.0, 0.0, 2.0), ankle);
+        let toe = spawn_bone(world, "toe", Vec3::new(0.0, 0.0, -0.3), ankle);
+        world.spawn((SkinnedMesh{ joints: vec![hip, knee, ankle, toe], ..default() }, ChildOf(armature)));
+        (scene_root, [hip, knee, ankle, toe])
+    }
+
+    #[test]
+    fn measures_the_bones_between_root_and_tip(){
+        let mut world = World::new();
+        let (scene_root, [hip, knee, ankle, toe]) = spawn_leg(&mut world);
+        SetupIkChain::new(scene_root, "hip", "ankle").apply(&mut world);
+
+        //each bone points at its child, the tip at its first child that's a skin joint
+        for (bone, length, bone_axis) in [(hip, 0.5, Vec3::Y), (knee, 0.4, Vec3::X), (ankle, 0.3, Vec3::NEG_Z)] {
+            let joint = world.get::<Joint>(bone).unwrap();
+            assert!((joint.length - length).abs() < EPSILON);
+            assert!(joint.bone_axis.abs_diff_eq(bone_axis, EPSILON));
+            assert!(joint.secondary_axis.dot(bone_axis).abs() < EPSILON);
+        }
+        assert!(world.get::<Joint>(toe).is_none());
+
+        assert!(world.get::<JointParent>(hip).is_none());
+        assert_eq!(world.get::<JointParent>(knee).map(|parent| parent.0), Some(hip));
+        assert_eq!(world.get::<JointParent>(ankle).map(|parent| parent.0), Some(knee));
+
+        //the base is spawned where the root bone is and the end effector at the end of the tip bone
+        let base = world.get::<BaseJoint>(hip).unwrap().0;
+        assert!(world.get::<Transform>(base).unwrap().translation.abs_diff_eq(Vec3::new(0.0, 1.0, 0.0), EPSILON));
+        let end_effector = world.get::<EEJoint>(ankle).unwrap().0;
+        assert!(world.get::<EndEffector>(end_effector).is_some());
+        assert!(world.get::<Transform>(end_effector).unwrap().translation.abs_diff_eq(Vec3::new(0.4, 1.5, -0.3), EPSILON));
+    }
+
+    #[test]
+    fn leaves_bones_that_are_not_above_each_other_alone(){
+        let mut world = World::new();
+        let (scene_root, bones) = spawn_leg(&mut world);
+        SetupIkChain::new(scene_root, "ankle", "hip").apply(&mut world);
+
+        assert!(bones.iter().all(|bone| world.get::<Joint>(*bone).is_none()));
+    }
+}
@@ -1,472 +1,1373 @@
-use super::{
-    BaseJoint, EEJoint, Joint, JointBookkeeping, JointChildren, JointParent,
-    IkGlobalSettings, RotationConstraint
-};
+use super::{EffectorMode, FabrikBase, EndEffector, FabrikChain, Joint, JointLock, JointTransform, FabrikTarget, IkChainConverged, IkChainSettings, IkChainStalled, IkChainState, IkEffectorState, IkGlobalSettings, IkTargetReached, IkTargetUnreachable, JointBookkeeping};
 
 use crate::constraint::*;
 
-use bevy::{ecs::entity::EntityHashSet, prelude::*};
+use bevy::prelude::*;
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex, RwLock};
 
 use crate::utils::*;
 
 pub fn solve(
     mut bk: ResMut<JointBookkeeping>,
-    global_joint_settings: Res<IkGlobalSettings>,
-    mut hierarchy_q: Query<(Entity, AnyOf<(&JointParent, &JointChildren)>)>,
-    mut top_joints: Query<Entity, (With<EEJoint>, Without<JointChildren>)>,
-    mut bottom_joints: Query<(Entity, &BaseJoint), (With<BaseJoint>, Without<JointParent>)>,
-    mut effector_joints: Query<(Entity, &EEJoint)>,
-    mut constraint_q: Query<&RotationConstraint, With<Joint>>,
+    global_settings: Res<IkGlobalSettings>,
+    chain_state_q: Query<&IkChainState>,
+    effector_state_q: Query<&IkEffectorState>,
+    mut commands: Commands,
 ) {
+    //chains don't share any data, so each one gets solved on its own thread without locking anything
+    bk.chains.par_iter_mut().for_each(|chain| {
+        if chain.invalid.is_none() {
+            solve_chain(&mut chain.fabrik, chain.settings, &global_settings);
+        }
+    });
+
+    //events only fire when something changes, the components still hold last frame's states here
+    for chain in bk.chains.iter() {
+        let state = chain.fabrik.state;
+        let solved = chain.invalid.is_none() && chain.settings.enabled && chain.settings.iterations.unwrap_or(global_settings.iterations) > 0;
+        if solved && state.converged && !chain_state_q.get(chain.root).is_ok_and(|old| old.converged) {
+            commands.trigger(IkChainConverged{ entity: chain.root, error: state.error, iterations_used: state.iterations_used });
+        }
+        if solved && state.stalled && !chain_state_q.get(chain.root).is_ok_and(|old| old.stalled) {
+            commands.trigger(IkChainStalled{ entity: chain.root, error: state.error, iterations_used: state.iterations_used });
+        }
+        commands.entity(chain.root).try_insert(state);
 
-    quat_unroll(&mut bk, bottom_joints.reborrow(), hierarchy_q.reborrow());
-
-    for _ in 0..global_joint_settings.iterations {
-
-        forward_reach(&mut bk, top_joints.reborrow(), hierarchy_q.reborrow(), effector_joints.reborrow(), constraint_q.reborrow());
-
-        let new_dist = backward_reach(&mut bk, bottom_joints.reborrow(), hierarchy_q.reborrow(), effector_joints.reborrow(), constraint_q.reborrow());
-        if bk.last_diff.distance(new_dist) < global_joint_settings.minimum_tolerance {
-            bk.last_diff = new_dist;
-            break;
+        if !solved {
+            continue;
+        }
+        for (i, effectors) in chain.effector_entities.iter().enumerate() {
+            for (effector, effector_state) in effectors.iter().copied().zip(chain.fabrik.effector_states[i].iter().copied()) {
+                let old = effector_state_q.get(effector).ok();
+                if effector_state.reached && !old.is_some_and(|old| old.reached) {
+                    commands.trigger(IkTargetReached{ entity: effector, joint: chain.entities[i] });
+                }
+                if !effector_state.reachable && old.is_none_or(|old| old.reachable) {
+                    commands.trigger(IkTargetUnreachable{ entity: effector, joint: chain.entities[i] });
+                }
+                commands.entity(effector).try_insert(effector_state);
+            }
         }
-        bk.last_diff = new_dist;
     }
-    
 }
 
-//once at the start or at every iteration before the forward reach?
-fn quat_unroll(
-    bk: &mut JointBookkeeping,
-    bottom_joints: Query<(Entity, &BaseJoint), (With<BaseJoint>, Without<JointParent>)>,
-    hierarchy_q: Query<(Entity, AnyOf<(&JointParent, &JointChildren)>)>,
-) {
-    let mut current: Vec<Entity> = bottom_joints.into_iter().map(|(entity, _)| entity).collect();
+impl FabrikChain{
+    //solves the chain from its current transforms towards the targets, the same way the plugin does, and leaves the solved pose in transforms.
+    //Settings left at None use IkGlobalSettings' defaults
+    pub fn solve(&mut self, targets: &[FabrikTarget], settings: IkChainSettings) -> IkChainState{
+        self.set_targets(targets);
+        solve_chain(self, settings, &IkGlobalSettings::default());
+        self.state
+    }
+}
+
+fn solve_chain(chain: &mut FabrikChain, settings: IkChainSettings, global_settings: &IkGlobalSettings) {
+    //stalling is only checked between iterations of the same solve, last frame's error says nothing about this one
+    chain.state = IkChainState{ error: f32::INFINITY, ..default() };
+    let iterations = settings.iterations.unwrap_or(global_settings.iterations);
+    let minimum_tolerance = settings.minimum_tolerance.unwrap_or(global_settings.minimum_tolerance);
+    if !settings.enabled || iterations == 0 {
+        return;
+    }
 
-    let seen = Arc::new(RwLock::new(EntityHashSet::new()));
+    quat_unroll(chain);
+
+    let resized = resize_joints(chain);
+    let stretchy = resized && chain.joints.iter().any(|joint| joint.can_stretch());
+
+    move_bases(chain);
+
+    let mut iteration = 0;
     loop {
-        let next = Arc::new(Mutex::new(Vec::new()));
+        //the effective lengths are measured from where the bases are now
+        if stretchy {
+            stretch_joints(chain);
+        }
 
-        current.par_iter().for_each(|main_entity| {
-            seen.write().unwrap().insert(*main_entity);
+        forward_reach(chain);
 
-            let mut main_transform = bk.joints.lock().unwrap().get(main_entity).unwrap().1;
+        pole_reach(chain);
 
-            if let Ok((_, (parent_maybe, child_maybe))) = hierarchy_q.get(*main_entity) {
-                if let Some(parent) = parent_maybe {
-                    let parent_transform = bk.joints.lock().unwrap().get(&parent.0).unwrap().1;
+        aim_reach(chain);
 
-                    if main_transform.rotation.dot(parent_transform.rotation) < 0.0{
-                        main_transform.rotation = -main_transform.rotation;
-                    }
-                } else {
-                    main_transform.rotation = quat_abs(main_transform.rotation);
+        let new_error = backward_reach(chain);
 
-                    
-                }
-                if let Some(children) = child_maybe{
-                    for child in children.0.iter() {
-                        if !seen.read().unwrap().contains(child) {
-                            next.lock().unwrap().push(*child);
-                        }
-                    }
-                }
-            }
+        iteration += 1;
 
-            bk.joints.lock().unwrap().get_mut(main_entity).unwrap().1 = main_transform;
+        //the chain stops once it converges, stops getting any closer or runs out of iterations
+        let state = &mut chain.state;
+        state.converged = new_error <= minimum_tolerance;
+        state.stalled = !state.converged && (state.error - new_error).abs() < minimum_tolerance;
+        state.error = new_error;
+        state.iterations_used = iteration;
 
-            
-        });
-        {
-            current.clear();
-            let mut next_lock = next.lock().unwrap();
-            current.append(&mut next_lock);
+        if state.converged || state.stalled || iteration >= iterations {
+            break;
         }
+    }
 
-        
-        if current.is_empty() {
-            break;
+    update_effector_states(chain, settings.reach_tolerance.unwrap_or(global_settings.reach_tolerance), stretchy);
+
+    //back to their own lengths, the stretch ends up in the scale
+    if resized {
+        chain.joints.copy_from_slice(&chain.scratch_joints);
+        for i in 0..chain.len() {
+            let joint = chain.joints[i];
+            if joint.can_stretch() {
+                chain.transforms[i].scale = joint.stretched_scale(chain.transforms[i].scale, chain.stretches[i]);
+            }
         }
     }
-    
 }
 
-fn forward_reach(
-    bk: &mut JointBookkeeping,
-    top_joints: Query<Entity, (With<EEJoint>, Without<JointChildren>)>,
-    hierarchy_q: Query<(Entity, AnyOf<(&JointParent, &JointChildren)>)>,
-    effector_joints: Query<(Entity, &EEJoint)>, //includes effector joints that are not at the end of any chain
-    constraint_q: Query<&RotationConstraint, With<Joint>>,
-) {
-    
-    //is cloning really the best choice? ill have to benchmark at some point
-    let mut current: Vec<Entity> = top_joints.into_iter().collect();
-    let seen = Arc::new(RwLock::new(EntityHashSet::new()));
+//how far each end effector is from its joint, and whether the joints between it and the base could reach it at all.
+//The error is measured at the lengths the joints were solved at, reachability from their own lengths fully stretched so a
+//joint that already stretched doesn't count twice
+fn update_effector_states(chain: &mut FabrikChain, reach_tolerance: f32, stretched: bool) {
+    let mut effector_states = std::mem::take(&mut chain.effector_states);
+    let rest_joints = if stretched { &chain.scratch_sized_joints } else { &chain.joints };
+    for (i, states) in effector_states.iter_mut().enumerate() {
+        states.clear();
+        for (ee, ee_transform) in chain.effectors[i].iter() {
+            let joint = chain.joints[i];
+            let reach_fraction = if ee.joint_center { 0.5 } else { 1.0 };
+            let error = effector_error(&joint, &chain.transforms[i], ee, ee_transform);
+
+            //anything can be aimed at, otherwise it's measured from the nearest base below the joint
+            let reachable = ee.mode != EffectorMode::Reach || nearest_base(chain, rest_joints, i, reach_fraction, true).is_none_or(|(base_index, reach)| {
+                let Some((base, base_transform)) = chain.bases[base_index] else { return true };
+                let excess = ee_transform.translation.distance(base_transform.translation) - reach;
+                excess - base_give(&base, excess) <= reach_tolerance
+            });
+
+            states.push(IkEffectorState{
+                error,
+                reached: error <= reach_tolerance,
+                reachable,
+            });
+        }
+    }
+    chain.effector_states = effector_states;
+}
 
-    loop {
-        let next = Arc::new(Mutex::new(Vec::new()));
-
-        current.par_iter().for_each(|main_entity| {
-            seen.write().unwrap().insert(*main_entity);
-            let mut main_transform = bk.joints.lock().unwrap().get(main_entity).unwrap().1;
-            let initial_rot = main_transform.rotation;
-            let main_joint = bk.joints.lock().unwrap().get(main_entity).unwrap().0;
-            let mut ee_c: usize = 0;
-            let mut children_c = 0;
-
-            let initial_bottom_point = main_transform.translation - (main_transform.rotation * main_joint.visual_offset);
-            let main_forward = main_transform.local_z().as_vec3();
-
-            let mut avg_top = Vec3::ZERO;
-            let mut anchor_total = Vec3::ZERO;
-            let mut rots = vec![];
-            let mut weights = vec![];
-            let mut total_weight = 0.0;
-
-            let identity = if let Ok(constraint) = constraint_q.get(*main_entity) {
-                constraint.identity.normalize()
+//once at the start or at every iteration before the forward reach?
+fn quat_unroll(chain: &mut FabrikChain) {
+    for i in 0..chain.len() {
+        let rotation = chain.transforms[i].rotation;
+        chain.transforms[i].rotation = match chain.parents[i] {
+            Some(parent) if rotation.dot(chain.transforms[parent].rotation) < 0.0 => -rotation,
+            Some(_) => rotation,
+            None => quat_abs(rotation),
+        };
+    }
+}
+
+//leaf to root, every joint points towards its end effector and the average of its children's bottoms
+fn forward_reach(chain: &mut FabrikChain) {
+    let mut rots = std::mem::take(&mut chain.scratch_rots);
+    let mut weights = std::mem::take(&mut chain.scratch_weights);
+
+    //joints that nothing above them is pulling on aren't in the order, so they stay where they are
+    for order_index in 0..chain.forward_order.len() {
+        let i = chain.forward_order[order_index];
+        //pinned joints stay put, their parents still point towards them like any other child
+        if chain.locks[i] == JointLock::Pinned {
+            continue;
+        }
+
+        let main_joint = chain.joints[i];
+        let mut main_transform = chain.transforms[i];
+        let initial_rot = main_transform.rotation;
+
+        //a joint with a base above the root is turned around its base instead, so the joints below reach for it
+        let anchor = chain.parents[i].and(chain.bases[i]).map(|(_, base_transform)| base_anchor(&base_transform, &main_joint, chain.scratch_base_offsets[i]));
+        let initial_bottom_point = anchor.unwrap_or(main_transform.translation - (main_transform.rotation * main_joint.visual_offset));
+        let main_forward = main_joint.forward(main_transform.rotation);
+
+        let mut avg_top = Vec3::ZERO;
+        let mut anchor_total = Vec3::ZERO;
+        rots.clear();
+        weights.clear();
+        let mut total_weight = 0.0;
+
+        let identity = chain.constraints[i].map_or(Quat::IDENTITY, |constraint| constraint.identity.normalize());
+
+        //partial rotation weights blend from the joint's rest pose on its parent (or base), anything that moves
+        //with the solve would keep creeping towards the effector's rotation every iteration
+        let rest_parent = chain.parents[i].map(|parent| chain.transforms[parent].rotation).or(chain.bases[i].map(|(_, base_transform)| base_transform.rotation));
+        let rest = rest_parent.map_or(main_transform.rotation, |rest_parent| rest_parent * identity);
+        let matched = |ee_transform: &JointTransform, rotation_weight: f32| {
+            if rotation_weight > 0.0 { rest.slerp(ee_transform.rotation, rotation_weight) } else { main_transform.rotation }
+        };
+        let mut rotated = false;
+
+        //aim effectors are handled in their own pass
+        for (ee, ee_transform) in chain.effectors[i].iter().filter(|(ee, _)| ee.mode == EffectorMode::Reach) {
+            let position_weight = ee.position_weight.clamp(0.0, 1.0);
+            let rotation_weight = ee.rotation_weight.clamp(0.0, 1.0);
+            let matched_rot = matched(ee_transform, rotation_weight);
+            let possible_top_point = if ee.joint_center {
+                ee_transform.translation + main_joint.up(matched_rot) * main_joint.length * 0.5
             } else {
-                Quat::IDENTITY
+                ee_transform.translation
             };
 
-            if let Ok((_, ee_joint)) = effector_joints.get(*main_entity) {
-                ee_c = 1;
-                let ee = bk.ends.read().unwrap().get(&ee_joint.0).unwrap().0;
-                let ee_transform = bk.ends.read().unwrap().get(&ee_joint.0).unwrap().1;
+            if position_weight > 0.0 {
+                let dir = (possible_top_point - initial_bottom_point).normalize();
+                rots.push(quat_abs(main_joint.aligned(dir, main_forward)));
+                weights.push(position_weight);
+            }
+            rotated |= rotation_weight > 0.0;
+            avg_top += possible_top_point * position_weight;
+            total_weight += position_weight;
+        }
 
-                let (rot, possible_top_point) = if ee.joint_copy_rotation{
-                    let center = if ee.joint_center {
-                        ee_transform.translation + ee_transform.rotation * Vec3::Y * main_joint.length * 0.5
-                    } else {
-                        ee_transform.translation
-                    };
-                    (ee_transform.rotation, center)    
-                }else{
-                    if ee.joint_center {
-                        let dir = ((ee_transform.translation + main_transform.rotation * Vec3::Y * main_joint.length * 0.5) - initial_bottom_point).normalize();
-                        let new = Transform::IDENTITY.aligned_by(Vec3::Y, dir, Vec3::Z, main_forward).rotation;
-                        (new, ee_transform.translation + main_transform.rotation * Vec3::Y * main_joint.length * 0.5)
-                    } else {
-                        let dir = (ee_transform.translation - initial_bottom_point).normalize();
-                        let new = Transform::IDENTITY.aligned_by(Vec3::Y, dir, Vec3::Z, main_forward).rotation;
-                        (new, ee_transform.translation)
-                    }
-                };
-                
-                rots.push(quat_abs(rot));
-                weights.push(ee.weight);
-                avg_top += possible_top_point * ee.weight;
-                total_weight += ee.weight;
+        let children = &chain.children[i];
+        if !children.is_empty() {
+            //not really ideal to loop twice
+            for child in children {
+                let child_transform = chain.transforms[*child];
+                let child_joint = chain.joints[*child];
+                let child_bottom_point = child_transform.translation - (child_transform.rotation * child_joint.visual_offset);
+                let mut weight = 1.0;
+                if let Some(hinge) = chain.hinges[*child] {
+                    total_weight += hinge.weight;
+                    weight = hinge.weight;
+                } else if let Some(constraint) = chain.constraints[*child] {
+                    total_weight += constraint.weight;
+                    weight = constraint.weight;
+                } else {
+                    total_weight += 1.0;
+                }
+
+                anchor_total += child_joint.anchor_offset * weight;
+                avg_top += child_bottom_point * weight;
             }
-            
-            if let Ok((_, (parent_maybe, child_maybe))) = hierarchy_q.get(*main_entity) {
-                if let Some(children) = child_maybe {
-                    children_c = children.0.len();
-
-                    
-                    //not really ideal to loop twice
-                    for child in &children.0{
-                        let child_transform = bk.joints.lock().unwrap().get(child).unwrap().1;
-                        let child_joint = bk.joints.lock().unwrap().get(child).unwrap().0;
-                        let child_bottom_point = child_transform.translation - (child_transform.rotation * child_joint.visual_offset);
-                        let mut weight = 1.0;
-                        if let Ok(constraint) = constraint_q.get(*child){
-                            total_weight += constraint.weight;
-                            weight = constraint.weight;
-                        } else {
-                            total_weight += 1.0;
-                        }
-
-                        anchor_total += child_joint.anchor_offset * weight;
-                        avg_top += child_bottom_point * weight;
-                        
-                        
-                    }
 
-                    let pre_top = (avg_top + main_transform.rotation * anchor_total) / total_weight;
-                    let up_dir = (pre_top - initial_bottom_point).normalize();
+            let pre_top = (avg_top + main_transform.rotation * anchor_total) / total_weight;
+            let up_dir = (pre_top - initial_bottom_point).normalize();
 
-                    let local_up_dir = identity.inverse() * up_dir;
-                    
-                    let local_forward_dir = identity.inverse() * main_forward;
+            let local_up_dir = identity.inverse() * up_dir;
 
-                    children.0.iter().for_each(|child| {
-                        let child_transform = bk.joints.lock().unwrap().get(child).unwrap().1;
+            for child in children {
+                let (child_joint, child_transform) = (chain.joints[*child], chain.transforms[*child]);
 
-                        let (rot, weight) = if let Ok(constraint) = constraint_q.get(*child) {
+                let (rot, weight) = if let Some(hinge) = chain.hinges[*child] {
+                    //the parent bends the opposite way around the child's hinge, its rest frame lines up with the hinge
+                    let hinge_identity = hinge.identity.normalize();
+                    let hinge_axis = (child_transform.rotation * hinge.axis).normalize();
+                    let rest_up = main_joint.aligned(up_dir, main_forward) * hinge_identity * child_joint.bone_axis;
 
-                            let constrained_local_up = constrain_direction_ellipse(local_up_dir,constraint.identity.conjugate() * child_transform.local_y().as_vec3(), f32::max(constraint.x.y, 0.0000001),f32::max( constraint.z.y, 0.0000001), constraint.strength);
+                    let constrained_up = constrain_direction_hinge(rest_up, child_joint.up(child_transform.rotation), hinge_axis, -hinge.max, -hinge.min, hinge.strength);
 
-                            let constrained_local_forward = constrain_direction_cone(local_forward_dir, constraint.identity.conjugate() * child_transform.local_y().as_vec3(), f32::max( constraint.y.y, 0.0000001), constraint.strength);
+                    let rest_frame = Transform::IDENTITY.aligned_by(child_joint.bone_axis, constrained_up, hinge.axis, hinge_axis).rotation;
+                    (rest_frame * hinge_identity.inverse(), hinge.weight)
+                } else if let Some(constraint) = chain.constraints[*child] {
 
-                            let constrained_global_up = constraint.identity * constrained_local_up;
+                    //the parent is constrained relative to the child here, so the limits are flipped
+                    let constrained_local_up = constrain_direction_ellipse(local_up_dir, constraint.identity.conjugate() * child_joint.up(child_transform.rotation), constraint.identity.conjugate() * child_joint.right(child_transform.rotation), -constraint.x.yx(), -constraint.z.yx(), constraint.strength);
 
-                            let constrained_global_forward = constraint.identity * constrained_local_forward;
+                    let constrained_global_up = constraint.identity * constrained_local_up;
 
-                            let rot = Transform::IDENTITY.aligned_by(Vec3::Y, constrained_global_up, Vec3::Z, constrained_global_forward).rotation;
-                            (rot, constraint.weight)
-                        } else {
-                            let rot = Transform::IDENTITY.aligned_by(Vec3::Y, up_dir, Vec3::Z, main_forward).rotation;
-                            (rot, 1.0)
-                        };
-                        
+                    let swung_rot = main_joint.aligned(constrained_global_up, main_forward);
 
-                        rots.push(quat_abs(rot));
-                        weights.push(weight);
-                        
-                    });
-                }
-                if let Some(parent) = parent_maybe
-                    && !seen.read().unwrap().contains(&parent.0)
-                {
-                    next.lock().unwrap().push(parent.0);
-                }
-            }            
+                    //the parent twists around its own y to keep the child's twist within its limits
+                    let rest_frame = swung_rot * constraint.identity.normalize();
+                    let twist = twist_correction(rest_frame.inverse() * child_transform.rotation, child_joint.bone_axis, constraint.y.x, constraint.y.y, constraint.strength);
+                    let rot = rest_frame * Quat::from_axis_angle(child_joint.bone_axis, -twist) * constraint.identity.normalize().inverse();
 
-            
-            
+                    (rot, constraint.weight)
+                } else {
+                    let rot = main_joint.aligned(up_dir, main_forward);
+                    (rot, 1.0)
+                };
 
-            
+                rots.push(quat_abs(rot));
+                weights.push(weight);
+            }
+        }
 
-            //new
-            let final_rot = if (children_c + ee_c) <= 1 {
-                rots[0]
-            } else {
-                rotation_averaging(
-                    &rots,
-                    &weights,
-                    5,
-                    main_transform.rotation,
-                )
-            };
+        //an effector with both weights at zero doesn't pull on anything
+        let final_rot = if rots.is_empty() {
+            //it still has to get back onto its base
+            if anchor.is_none() && !rotated {
+                continue;
+            }
+            initial_rot
+        } else if chain.locks[i] == JointLock::Rotation {
+            initial_rot
+        } else if rots.len() == 1 {
+            rots[0]
+        } else {
+            rotation_averaging(
+                &rots,
+                &weights,
+                5,
+                main_transform.rotation,
+            )
+        };
+
+        //the end effectors' rotations go on after the aim, so a rotation_weight of 1 copies the rotation exactly. They blend
+        //once from the rest pose swung onto the aim, blending from the aim itself would compound every iteration
+        let final_rot = if chain.locks[i] == JointLock::Rotation || !rotated {
+            final_rot
+        } else {
+            let swung_rest = Quat::from_rotation_arc(main_joint.up(rest), main_joint.up(final_rot)) * rest;
+            rots.clear();
+            weights.clear();
+            for (ee, ee_transform) in chain.effectors[i].iter().filter(|(ee, _)| ee.mode == EffectorMode::Reach && ee.rotation_weight > 0.0) {
+                let rotation_weight = ee.rotation_weight.clamp(0.0, 1.0);
+                rots.push(quat_abs(swung_rest.slerp(ee_transform.rotation, rotation_weight)));
+                weights.push(rotation_weight);
+            }
+            if rots.len() == 1 { rots[0] } else { rotation_averaging(&rots, &weights, 5, final_rot) }
+        };
 
-            
+        //with nothing pulling on the top (a rotation only effector on a leaf), the joint turns around its bottom instead
+        let new_bottom_point = if let Some(anchor) = anchor {
+            anchor
+        } else if total_weight > 0.0 {
             anchor_total = final_rot * anchor_total;
 
             avg_top += anchor_total;
 
             avg_top /= total_weight;
 
-            let new_bottom_point = avg_top - (final_rot * Vec3::Y * main_joint.length);
+            avg_top - (main_joint.up(final_rot) * main_joint.length)
+        } else {
+            initial_bottom_point
+        };
+
+        let final_translation = new_bottom_point + (final_rot * main_joint.visual_offset);
+        main_transform.translation = final_translation;
+        if final_rot.dot(initial_rot) < 0.0{
+            main_transform.rotation = -final_rot;
+        } else {
+            main_transform.rotation = final_rot;
+        }
+
+        chain.transforms[i] = main_transform;
+    }
+
+    chain.scratch_rots = rots;
+    chain.scratch_weights = weights;
+}
+
+//bends the joints below each pole joint towards the plane made by the base, the end effector and the pole target. A segment
+//that stops at a branch uses its bottom joint in place of the base, a pole joint without an end effector reaching for
+//something uses its own top
+fn pole_reach(chain: &mut FabrikChain) {
+    let mut segment = std::mem::take(&mut chain.scratch_segment);
+    let mut bottoms = std::mem::take(&mut chain.scratch_points);
+
+    for pole_index in 0..chain.len() {
+        let Some((pole, pole_transform)) = chain.poles[pole_index] else { continue };
+        let weight = pole.weight.clamp(0.0, 1.0);
+        if weight <= 0.0 || chain.locks[pole_index] != JointLock::Free {
+            continue;
+        }
+
+        //walk down from the pole joint, stopping at a joint with a base or a joint with more than one child
+        segment.clear();
+        segment.push(pole_index);
+        let mut current = pole_index;
+        while pole.chain_length.is_none_or(|chain_length| segment.len() < chain_length) && chain.bases[current].is_none() {
+            let Some(parent) = chain.parents[current] else { break };
+            if chain.children[parent].len() > 1 || chain.locks[parent] != JointLock::Free {
+                break;
+            }
+            segment.push(parent);
+            current = parent;
+        }
+        //needs at least one joint in between to bend
+        if segment.len() < 2 {
+            continue;
+        }
+        segment.reverse();
+
+        let (root_joint, root_transform) = (chain.joints[segment[0]], chain.transforms[segment[0]]);
+        let (end_joint, end_transform) = (chain.joints[pole_index], chain.transforms[pole_index]);
+        let root = match chain.bases[segment[0]] {
+            Some((_, base_transform)) => base_anchor(&base_transform, &root_joint, chain.scratch_base_offsets[segment[0]]),
+            None => root_transform.translation - (root_transform.rotation * root_joint.visual_offset),
+        };
+        let end = end_transform.translation - (end_transform.rotation * end_joint.visual_offset) + (end_joint.up(end_transform.rotation) * end_joint.length);
+        let effector = chain.effectors[pole_index].iter()
+            .find(|(ee, _)| ee.mode == EffectorMode::Reach && ee.position_weight > 0.0)
+            .map_or(end, |(_, ee_transform)| ee_transform.translation);
+
+        let Some(axis) = (effector - root).try_normalize() else { continue };
+        let Some(pole_dir) = (pole_transform.translation - root).reject_from_normalized(axis).try_normalize() else { continue };
+
+        //the weight is how far the segment turns from the way it bent in the input pose, turning that part of
+        //what's left every iteration would compound until it lies in the pole plane
+        let input_bottom = |i: usize| chain.input_transforms[i].translation - (chain.input_transforms[i].rotation * chain.joints[i].visual_offset);
+        let input_root = input_bottom(segment[0]);
+        let input_bend = segment[1..].iter().map(|i| input_bottom(*i) - input_root).sum::<Vec3>();
+        let plane_dir = match input_bend.reject_from_normalized(axis).try_normalize() {
+            Some(input_dir) => Quat::from_axis_angle(axis, input_dir.cross(pole_dir).dot(axis).atan2(input_dir.dot(pole_dir)) * weight) * input_dir,
+            None => pole_dir,
+        };
+
+        //the bottom points of every joint after the first and the pole joint's top get projected onto that plane,
+        //the top only moves if the end effector is out of reach
+        bottoms.clear();
+        bottoms.extend(segment.iter().map(|i| chain.transforms[*i].translation - (chain.transforms[*i].rotation * chain.joints[*i].visual_offset)));
+        bottoms.push(end);
+        for bottom in bottoms.iter_mut().skip(1) {
+            let offset = *bottom - root;
+            let Some(bottom_dir) = offset.reject_from_normalized(axis).try_normalize() else { continue };
+            let angle = bottom_dir.cross(plane_dir).dot(axis).atan2(bottom_dir.dot(plane_dir));
+            *bottom = root + Quat::from_axis_angle(axis, angle) * offset;
+        }
+
+        for (i, joint_index) in segment.iter().enumerate() {
+            let joint = chain.joints[*joint_index];
+            let input_rot = chain.input_transforms[*joint_index].rotation;
+            let jt = &mut chain.transforms[*joint_index];
+            let top = bottoms[i + 1];
+            let Some(new_dir) = (top - bottoms[i]).try_normalize() else { continue };
+
+            //the twist blends from the input pose as well
+            let bent = Quat::from_rotation_arc(joint.up(input_rot), new_dir) * input_rot;
+            let facing = joint.aligned(new_dir, pole_dir);
+            let final_rot = bent.slerp(facing, weight).normalize();
+
+            jt.rotation = if final_rot.dot(jt.rotation) < 0.0 { -final_rot } else { final_rot };
+            jt.translation = bottoms[i] + (jt.rotation * joint.visual_offset);
+        }
+    }
 
+    chain.scratch_segment = segment;
+    chain.scratch_points = bottoms;
+}
+
+//turns the joints below each aim effector's joint, up to the nearest branch or its chain length, so the aim axis points at the effector.
+//Every joint turns its part of what's left around its own bottom, carrying everything above it along
+fn aim_reach(chain: &mut FabrikChain) {
+    let mut segment = std::mem::take(&mut chain.scratch_segment);
+    let mut subtree = std::mem::take(&mut chain.scratch_subtree);
+
+    for aim_index in 0..chain.len() {
+        //each aim effector takes its turn, so several on one joint end up somewhere in between
+        for effector_index in 0..chain.effectors[aim_index].len() {
+            let (ee, ee_transform) = chain.effectors[aim_index][effector_index];
+            let EffectorMode::Aim(axis) = ee.mode else { continue };
+            let Some(axis) = axis.try_normalize() else { continue };
+            let weight = ee.position_weight.clamp(0.0, 1.0);
+            if weight <= 0.0 || chain.locks[aim_index] != JointLock::Free {
+                continue;
+            }
+
+            segment.clear();
+            segment.push(aim_index);
+            let mut current = aim_index;
+            while ee.chain_length.is_none_or(|chain_length| segment.len() < chain_length)
+                && chain.bases[current].is_none()
+                && let Some(parent) = chain.parents[current]
+            {
+                if chain.children[parent].len() > 1 || chain.locks[parent] != JointLock::Free {
+                    break;
+                }
+                segment.push(parent);
+                current = parent;
+            }
+            segment.reverse();
+
+            //everything above the aim joint moves with it
+            subtree.clear();
+            subtree.push(aim_index);
+            let mut j = 0;
+            while j < subtree.len() {
+                subtree.extend_from_slice(&chain.children[subtree[j]]);
+                j += 1;
+            }
+
+            for k in 0..segment.len() {
+                let aim_transform = chain.transforms[aim_index];
+                let origin = aim_transform.translation - (aim_transform.rotation * chain.joints[aim_index].visual_offset);
+                let Some(target_dir) = (ee_transform.translation - origin).try_normalize() else { break };
+                let aim_dir = (aim_transform.rotation * axis).normalize();
+
+                //the weight is how far the axis turns from where it pointed in the input pose, turning that part of
+                //what's left every iteration would compound until it points right at the effector
+                let input_dir = (chain.input_transforms[aim_index].rotation * axis).normalize();
+                let weighted_dir = Quat::IDENTITY.slerp(Quat::from_rotation_arc(input_dir, target_dir), weight) * input_dir;
 
-            
+                //the remaining turn is split evenly between this joint and the ones above it
+                let turn = Quat::IDENTITY.slerp(Quat::from_rotation_arc(aim_dir, weighted_dir), 1.0 / (segment.len() - k) as f32);
+
+                let pivot_joint = chain.joints[segment[k]];
+                let pivot_transform = chain.transforms[segment[k]];
+                let pivot = pivot_transform.translation - (pivot_transform.rotation * pivot_joint.visual_offset);
+
+                for moved in segment[k..segment.len() - 1].iter().chain(subtree.iter()) {
+                    let lock = chain.locks[*moved];
+                    if lock == JointLock::Pinned {
+                        continue;
+                    }
+                    let jt = &mut chain.transforms[*moved];
+                    if lock == JointLock::Free {
+                        let rotation = (turn * jt.rotation).normalize();
+                        jt.rotation = if rotation.dot(jt.rotation) < 0.0 { -rotation } else { rotation };
+                    }
+                    jt.translation = pivot + turn * (jt.translation - pivot);
+                }
+            }
+        }
+    }
 
-            let final_translation = new_bottom_point + (final_rot * main_joint.visual_offset);
-            main_transform.translation = final_translation;
-            if final_rot.dot(initial_rot) < 0.0{
-                main_transform.rotation = -final_rot;
+    chain.scratch_segment = segment;
+    chain.scratch_subtree = subtree;
+}
+
+//where a joint with a base gets anchored, offset is how far move_bases moved it
+fn base_anchor(base_transform: &JointTransform, joint: &Joint, offset: Vec3) -> Vec3 {
+    base_transform.translation + (base_transform.rotation * joint.anchor_offset) + offset
+}
+
+//how much of the distance an end effector is out of reach by the base makes up for, by its weight and up to its radius
+fn base_give(base: &FabrikBase, excess: f32) -> f32 {
+    if base.radius <= 0.0 || base.weight <= 0.0 || excess <= 0.0 {
+        return 0.0;
+    }
+    (excess * base.weight.min(1.0)).min(base.radius)
+}
+
+//the nearest joint with a base going down from joint i, and how far the joints from it up to joint i reach laid end to end,
+//fully stretched if max_stretch is set
+fn nearest_base(chain: &FabrikChain, joints: &[Joint], i: usize, reach_fraction: f32, max_stretch: bool) -> Option<(usize, f32)> {
+    let length = |joint: Joint| if max_stretch && joint.can_stretch() { joint.length * joint.max_stretch.max(1.0) } else { joint.length };
+    let joint = joints[i];
+    let mut reach = length(joint) * reach_fraction + joint.anchor_offset.length();
+    let mut current = i;
+    loop {
+        if chain.bases[current].is_some() {
+            return Some((current, reach));
+        }
+        let parent = chain.parents[current]?;
+        reach += length(joints[parent]) + joints[parent].anchor_offset.length();
+        current = parent;
+    }
+}
+
+//lengths and offsets are in each joint's local space, so they're scaled by its global scale (the anchor offset by its
+//parent's, or its base's), stretching is left to stretch_joints. The joints are solved at that size and put back afterwards,
+//returns false if there was nothing to change
+fn resize_joints(chain: &mut FabrikChain) -> bool {
+    //scales that came out of a GlobalTransform are rarely exactly one
+    let unscaled = |scale: Vec3| scale.abs_diff_eq(Vec3::ONE, 1e-5);
+    let scaled = !chain.transforms.iter().all(|jt| unscaled(jt.scale))
+        || !chain.bases.iter().flatten().all(|(_, base_transform)| unscaled(base_transform.scale));
+    let stretchy = chain.joints.iter().any(|joint| joint.can_stretch());
+    chain.stretches.fill(1.0);
+    if !scaled && !stretchy {
+        return false;
+    }
+
+    chain.scratch_joints.clear();
+    chain.scratch_joints.extend_from_slice(&chain.joints);
+    if scaled {
+        for i in 0..chain.len() {
+            let parent_scale = match chain.parents[i] {
+                Some(parent) => chain.scratch_joints[parent].rest_scale(chain.transforms[parent].scale),
+                None => chain.bases[i].map_or(Vec3::ONE, |(_, base_transform)| base_transform.scale),
+            };
+            let joint = chain.scratch_joints[i];
+            chain.joints[i] = joint.scaled(joint.rest_scale(chain.transforms[i].scale), parent_scale);
+        }
+    }
+    chain.scratch_sized_joints.clear();
+    chain.scratch_sized_joints.extend_from_slice(&chain.joints);
+    true
+}
+
+//stretches the joints between each end effector and its base when the effector is out of reach, and squashes them when it's
+//closer than the chain can fold, the joints that can't stretch keep their length. Done every iteration from the unstretched
+//lengths, measured from where the bases have moved to
+fn stretch_joints(chain: &mut FabrikChain) {
+    let mut wanted = std::mem::take(&mut chain.scratch_stretches);
+    wanted.clear();
+    wanted.resize(chain.len(), None);
+
+    for i in 0..chain.len() {
+        for (ee, ee_transform) in chain.effectors[i].iter() {
+            if ee.mode != EffectorMode::Reach || ee.position_weight <= 0.0 {
+                continue;
+            }
+
+            //how much of the way to the base can stretch and how much can't
+            let mut fraction = if ee.joint_center { 0.5 } else { 1.0 };
+            let mut fixed = 0.0;
+            let mut stretchy = 0.0;
+            let mut longest: f32 = 0.0;
+            let mut longest_stretchy = false;
+            let mut current = i;
+            let base = loop {
+                let joint = chain.scratch_sized_joints[current];
+                fixed += joint.anchor_offset.length();
+                if joint.can_stretch() {
+                    stretchy += joint.length * fraction;
+                } else {
+                    fixed += joint.length * fraction;
+                }
+                if joint.anchor_offset.length() > longest {
+                    longest = joint.anchor_offset.length();
+                    longest_stretchy = false;
+                }
+                if joint.length * fraction > longest {
+                    longest = joint.length * fraction;
+                    longest_stretchy = joint.can_stretch();
+                }
+                fraction = 1.0;
+                if let Some((_, base_transform)) = chain.bases[current] {
+                    break Some((current, base_transform));
+                }
+                let Some(parent) = chain.parents[current] else { break None };
+                current = parent;
+            };
+            let Some((base_index, base_transform)) = base else { continue };
+            if stretchy <= 0.0 {
+                continue;
+            }
+
+            //only out of reach or closer than the fully folded chain gets to (the longest piece folded back over the rest)
+            //changes anything, the joints on the way are stretched for whichever end effector needs the most
+            let distance = ee_transform.translation.distance(base_transform.translation + chain.scratch_base_offsets[base_index]);
+            let rest = fixed + stretchy;
+            let folded = 2.0 * longest - rest;
+            let ratio = if distance > rest {
+                (distance - fixed) / stretchy
+            } else if distance < folded && longest_stretchy {
+                //squashing only helps if the longest piece squashes with the rest
+                ((distance + fixed) / (2.0 * longest - stretchy)).max(0.0)
             } else {
-                main_transform.rotation = final_rot;     
+                1.0
+            };
+            let mut current = i;
+            loop {
+                wanted[current] = Some(wanted[current].map_or(ratio, |other| other.max(ratio)));
+                if current == base_index {
+                    break;
+                }
+                let Some(parent) = chain.parents[current] else { break };
+                current = parent;
+            }
+        }
+    }
+
+    for (i, wanted) in wanted.iter().enumerate() {
+        let joint = chain.scratch_sized_joints[i];
+        let stretch = match *wanted {
+            Some(ratio) if joint.can_stretch() => {
+                let ratio = ratio.lerp(1.0, joint.stretch_stiffness.clamp(0.0, 1.0));
+                ratio.clamp(joint.min_stretch.min(1.0), joint.max_stretch.max(1.0))
             }
-            
+            _ => 1.0,
+        };
+        chain.stretches[i] = stretch;
+        chain.joints[i] = joint.stretched(stretch);
+    }
+
+    chain.scratch_stretches = wanted;
+}
 
-            bk.joints.lock().unwrap().get_mut(main_entity).unwrap().1 = main_transform;
-            
-        });
-        {
-            current.clear();
-            let mut next_lock = next.lock().unwrap();
-            current.append(&mut next_lock);
+//moves every base with a radius towards whichever of its end effectors is furthest out of reach, once per solve,
+//so bases stay where they are as long as everything can be reached
+fn move_bases(chain: &mut FabrikChain) {
+    let mut offsets = std::mem::take(&mut chain.scratch_base_offsets);
+    offsets.clear();
+    offsets.resize(chain.len(), Vec3::ZERO);
+
+    for i in 0..chain.len() {
+        for (ee, ee_transform) in chain.effectors[i].iter() {
+            if ee.mode != EffectorMode::Reach || ee.position_weight <= 0.0 {
+                continue;
+            }
+            let reach_fraction = if ee.joint_center { 0.5 } else { 1.0 };
+            //stiffness is a choice to fall short, the base only makes up for what the joints can't stretch to
+            let Some((base_index, reach)) = nearest_base(chain, &chain.joints, i, reach_fraction, true) else { continue };
+            let Some((base, base_transform)) = chain.bases[base_index] else { continue };
+
+            let to_effector = ee_transform.translation - base_transform.translation;
+            let excess = to_effector.length() - reach;
+            let offset = to_effector.normalize_or_zero() * base_give(&base, excess);
+            if offset.length_squared() > offsets[base_index].length_squared() {
+                offsets[base_index] = offset;
+            }
         }
+    }
 
-        
-        if current.is_empty() {
-            break;
+    chain.scratch_base_offsets = offsets;
+}
+
+//how far an end effector is from where its joint reaches for it, for aim effectors how far it is off the aim axis
+fn effector_error(joint: &Joint, jt: &JointTransform, ee: &EndEffector, ee_transform: &JointTransform) -> f32 {
+    let bottom = jt.translation - (jt.rotation * joint.visual_offset);
+    match ee.mode {
+        EffectorMode::Reach => {
+            let reach_point = bottom + joint.up(jt.rotation) * joint.length * if ee.joint_center { 0.5 } else { 1.0 };
+            ee_transform.translation.distance(reach_point)
+        }
+        EffectorMode::Aim(axis) => {
+            let offset = ee_transform.translation - bottom;
+            let Some(axis) = (jt.rotation * axis).try_normalize() else { return 0.0 };
+            if offset.dot(axis) > 0.0 {
+                offset.reject_from_normalized(axis).length()
+            } else {
+                offset.length()
+            }
         }
     }
 }
 
-fn backward_reach(
-    bk: &mut JointBookkeeping,
-    bottom_joints: Query<(Entity, &BaseJoint), (With<BaseJoint>, Without<JointParent>)>,
-    hierarchy_q: Query<(Entity, AnyOf<(&JointParent, &JointChildren)>)>,
-    effector_joints: Query<(Entity, &EEJoint)>,
-    constraint_q: Query<&RotationConstraint, With<Joint>>,
-) -> Vec3 {
-    let mut current: Vec<Entity> = vec![];
-    let seen = Arc::new(RwLock::new(EntityHashSet::new()));
-    let end_dists = Arc::new(Mutex::new(Vec3::ZERO));
-    for (main_entity, base_joint) in bottom_joints.iter() {
-        if let Ok((_, (_, child_maybe))) = hierarchy_q.get(main_entity) {
-            if let Some(children) = child_maybe {
-                let main_joint = bk.joints.lock().unwrap().get(&main_entity).unwrap().0;
-                let mut main_transform = bk.joints.lock().unwrap().get(&main_entity).unwrap().1;
-                let base_transform = bk.bases.read().unwrap().get(&base_joint.0).unwrap().1;
-                let new_bottom_point = base_transform.translation;
+//root to leaf, pins every joint with a base back onto it and every other joint onto its parent's top,
+//returns the furthest any end effector is from its joint, or a joint with a base from its parent's top
+fn backward_reach(chain: &mut FabrikChain) -> f32 {
+    //a chain without a base just follows its end effectors, nothing gets pulled back but they can still be out of reach
+    if chain.bases.iter().all(Option::is_none) {
+        let mut end_dist: f32 = 0.0;
+        for i in 0..chain.len() {
+            for (ee, ee_transform) in chain.effectors[i].iter() {
+                end_dist = end_dist.max(effector_error(&chain.joints[i], &chain.transforms[i], ee, ee_transform));
+            }
+        }
+        return end_dist;
+    }
+
+    let mut end_dist: f32 = 0.0;
+
+    for i in 0..chain.len() {
+        let main_joint = chain.joints[i];
+        let mut main_transform = chain.transforms[i];
+        let main_forward = main_joint.forward(main_transform.rotation);
+
+        //pinned joints aren't moved, the joints above them get anchored onto them instead,
+        //same for a root without a base, which was already moved by the forward reach
+        if chain.locks[i] == JointLock::Pinned || (chain.parents[i].is_none() && chain.bases[i].is_none()) {
+            for (ee, ee_transform) in chain.effectors[i].iter() {
+                end_dist = end_dist.max(effector_error(&main_joint, &main_transform, ee, ee_transform));
+            }
+            continue;
+        }
+
+        //remove visual offset from here aswell
+        let main_real_t = main_transform.translation - (main_transform.rotation * main_joint.visual_offset);
+        let main_top = main_real_t + (main_joint.up(main_transform.rotation) * main_joint.length);
+
+        let (anchor_pos, final_rot) = if let Some(parent) = chain.parents[i] {
+            let parent_transform = chain.transforms[parent];
+            let parent_joint = chain.joints[parent];
+            let parent_identity = chain.constraints[parent].map_or(Quat::IDENTITY, |constraint| constraint.identity.normalize());
+
+            //remove visual offset from translation
+            let parent_real_t = parent_transform.translation - (parent_transform.rotation * parent_joint.visual_offset);
+
+            let parent_top = parent_real_t + (parent_joint.up(parent_transform.rotation) * parent_joint.length);
+            let mut anchor_pos = parent_top + (parent_transform.rotation * main_joint.anchor_offset);
+
+            //a joint with its own base closes the loop, whatever gap is left to its parent counts as error
+            if let Some((_, base_transform)) = chain.bases[i] {
+                let base_pos = base_anchor(&base_transform, &main_joint, chain.scratch_base_offsets[i]);
+                end_dist = end_dist.max(base_pos.distance(anchor_pos));
+                anchor_pos = base_pos;
+            }
+
+            let up_dir = (main_top - anchor_pos).normalize();
+
+            let final_rot = if let Some(hinge) = chain.hinges[i] {
+                let rest_frame = parent_transform.rotation * hinge.identity.normalize();
+                let hinge_axis = (rest_frame * hinge.axis).normalize();
+
+                let constrained_up = constrain_direction_hinge(up_dir, rest_frame * main_joint.bone_axis, hinge_axis, hinge.min, hinge.max, hinge.strength);
+
+                Transform::IDENTITY.aligned_by(main_joint.bone_axis, constrained_up, hinge.axis, hinge_axis).rotation
+            } else if let Some(constraint) = chain.constraints[i] {
+                let local_up_dir = (constraint.identity.inverse() * up_dir).normalize();
+
+                let constrained_local_up = constrain_direction_ellipse(local_up_dir, parent_identity.inverse() * parent_joint.up(parent_transform.rotation), parent_identity.inverse() * parent_joint.right(parent_transform.rotation), constraint.x, constraint.z, constraint.strength);
+
+                let constrained_global_up = constraint.identity * constrained_local_up;
+
+                let swung_rot = main_joint.aligned(constrained_global_up, main_forward);
+
+                //twist is clamped separately, relative to the parent's rotation as the rest pose
+                let rest_frame = parent_transform.rotation * constraint.identity.normalize();
+                let twist = twist_correction(rest_frame.inverse() * swung_rot, main_joint.bone_axis, constraint.y.x, constraint.y.y, constraint.strength);
+                swung_rot * Quat::from_axis_angle(main_joint.bone_axis, twist)
+            } else {
+                main_joint.aligned(up_dir, main_forward)
+            };
+
+            (anchor_pos, final_rot)
+        } else {
+            let Some((_, base_transform)) = chain.bases[i] else { continue };
+            let anchor_pos = base_anchor(&base_transform, &main_joint, chain.scratch_base_offsets[i]);
+
+            //the base joint points towards the average of its children's bottoms, or its own top if it has none
+            let children = &chain.children[i];
+            let up_dir = if children.is_empty() {
+                (main_top - anchor_pos).normalize()
+            } else {
                 let mut avg_top = Vec3::ZERO;
                 let mut anchor_total = Vec3::ZERO;
                 let mut total_weight = 0.0;
-                
-                for child in children.0.iter(){
-                    let child_transform = bk.joints.lock().unwrap().get(child).unwrap().1;
-                    let child_joint = bk.joints.lock().unwrap().get(child).unwrap().0;
+
+                for child in children {
+                    let child_transform = chain.transforms[*child];
+                    let child_joint = chain.joints[*child];
                     let child_bottom_point = child_transform.translation - (child_transform.rotation * child_joint.visual_offset);
-                    let mut weight = 1.0;
-                    if let Ok(constraint) = constraint_q.get(*child) {
-                        
-                        weight = constraint.weight;
-                    }
+                    let weight = if let Some(hinge) = chain.hinges[*child] {
+                        hinge.weight
+                    } else if let Some(constraint) = chain.constraints[*child] {
+                        constraint.weight
+                    } else {
+                        1.0
+                    };
                     total_weight += weight;
 
                     anchor_total += child_joint.anchor_offset * weight;
                     avg_top += child_bottom_point * weight;
-                    current.push(*child);
                 }
 
-                let anchor_pos = new_bottom_point + (base_transform.rotation * main_joint.anchor_offset);
                 let pre_top = (avg_top + main_transform.rotation * anchor_total) / total_weight;
-                let up_dir = (pre_top - anchor_pos).normalize();
+                (pre_top - anchor_pos).normalize()
+            };
 
-                let main_forward = main_transform.local_z().as_vec3();
+            let final_rot = if let Some(hinge) = chain.hinges[i] {
+                let rest_frame = base_transform.rotation * hinge.identity.normalize();
+                let hinge_axis = (rest_frame * hinge.axis).normalize();
 
-                if let Ok(constraint) = constraint_q.get(main_entity){
-                    let local_up_dir = (constraint.identity.inverse() * up_dir).normalize();
-                    let local_forward_dir = (constraint.identity.inverse() * main_forward).normalize();
-                            
-                    let constrained_local_up = constrain_direction_ellipse(local_up_dir, base_transform.local_y().as_vec3(), f32::max(constraint.x.y, 0.0000001),f32::max( constraint.z.y, 0.0000001), constraint.strength);
+                let constrained_up = constrain_direction_hinge(up_dir, rest_frame * main_joint.bone_axis, hinge_axis, hinge.min, hinge.max, hinge.strength);
 
-                    let constrained_local_forward = constrain_direction_cone(local_forward_dir, base_transform.local_z().as_vec3(), f32::max( constraint.y.y, 0.0000001), constraint.strength);
+                Transform::IDENTITY.aligned_by(main_joint.bone_axis, constrained_up, hinge.axis, hinge_axis).rotation
+            } else if let Some(constraint) = chain.constraints[i] {
+                let local_up_dir = (constraint.identity.inverse() * up_dir).normalize();
 
-                    let constrained_global_up = constraint.identity * constrained_local_up;
+                let constrained_local_up = constrain_direction_ellipse(local_up_dir, main_joint.up(base_transform.rotation), main_joint.right(base_transform.rotation), constraint.x, constraint.z, constraint.strength);
 
-                    let constrained_global_forward = constraint.identity * constrained_local_forward;
+                let constrained_global_up = constraint.identity * constrained_local_up;
 
-                    let final_rot = Transform::IDENTITY.aligned_by(Vec3::Y, constrained_global_up, Vec3::Z, constrained_global_forward).rotation;
+                let swung_rot = main_joint.aligned(constrained_global_up, main_forward);
 
-                                                    
-                    if final_rot.dot(main_transform.rotation) < 0.0{
-                        main_transform.rotation = -final_rot;
-                    } else {
-                        main_transform.rotation = final_rot;
-                    }
-                } else {
-                    let final_rot = Quat::from_rotation_arc(Vec3::Y, up_dir);
-                        
-                    if final_rot.dot(main_transform.rotation) < 0.0{
-                        main_transform.rotation = -final_rot;
-                    } else {
-                        main_transform.rotation = final_rot;
-                    }
-                    
-                }
-                
+                //twist is clamped separately, relative to the base's rotation as the rest pose
+                let rest_frame = base_transform.rotation * constraint.identity.normalize();
+                let twist = twist_correction(rest_frame.inverse() * swung_rot, main_joint.bone_axis, constraint.y.x, constraint.y.y, constraint.strength);
+                swung_rot * Quat::from_axis_angle(main_joint.bone_axis, twist)
+            } else {
+                Quat::from_rotation_arc(main_joint.bone_axis, up_dir)
+            };
 
-                main_transform.translation = anchor_pos + (main_transform.rotation * main_joint.visual_offset);
+            (anchor_pos, final_rot)
+        };
 
-                if let Ok((_, ee_joint)) = effector_joints.get(main_entity) {
-                    let (_, ee_transform) = *bk.ends.read().unwrap().get(&ee_joint.0).unwrap();
-                    let dist = ee_transform.translation - main_transform.translation;
-                    *end_dists.lock().unwrap() += dist;
-                }
+        //a locked joint is only moved onto its anchor
+        if chain.locks[i] != JointLock::Rotation {
+            main_transform.rotation = if final_rot.dot(main_transform.rotation) < 0.0 { -final_rot } else { final_rot };
+        }
+
+        //add visual offset into main
+        main_transform.translation = anchor_pos + (main_transform.rotation * main_joint.visual_offset);
+
+        for (ee, ee_transform) in chain.effectors[i].iter() {
+            end_dist = end_dist.max(effector_error(&main_joint, &main_transform, ee, ee_transform));
+        }
+
+        chain.transforms[i] = main_transform;
+    }
+
+    end_dist
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{BaseJoint, EEJoint, FabrikPole, HingeConstraint, IkDisabled, IkSolverPlugin, IkWeight, JointParent, RotationConstraint};
+
+    const EPSILON: f32 = 0.01;
+
+    fn joint() -> Joint{
+        Joint{ length: 1.0, ..default() }
+    }
+
+    //count joints in a straight line up the y axis, with the root pinned to a base at the origin
+    fn straight_chain(count: usize, joint: Joint) -> FabrikChain{
+        let mut chain = FabrikChain::new();
+        let mut parent = None;
+        for i in 0..count {
+            parent = chain.add_joint(joint, Transform::from_xyz(0.0, joint.length * i as f32, 0.0).into(), parent);
+        }
+        chain.set_base(0, JointTransform::IDENTITY, 0.0, 1.0);
+        chain
+    }
+
+    fn app() -> App{
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, TransformPlugin, IkSolverPlugin));
+        app.update();
+        app
+    }
+
+    //count joints in a straight line up from a base at the given position, with an end effector on the last one.
+    //Returns the joints and the end effector
+    fn spawn_chain(world: &mut World, count: usize, start: Vec3, end: Vec3) -> (Vec<Entity>, Entity){
+        let base = world.spawn(Transform::from_translation(start)).id();
+        let effector = world.spawn((EndEffector::default(), Transform::from_translation(end))).id();
+        let mut joints: Vec<Entity> = Vec::new();
+        for i in 0..count {
+            let mut entity = world.spawn((joint(), Transform::from_translation(start + Vec3::Y * i as f32)));
+            match joints.last() {
+                Some(parent) => entity.insert(JointParent(*parent)),
+                None => entity.insert(BaseJoint(base)),
+            };
+            joints.push(entity.id());
+        }
+        world.entity_mut(joints[count - 1]).insert(EEJoint(effector));
+        (joints, effector)
+    }
+
+    fn reach(joint: usize, translation: Vec3) -> FabrikTarget{
+        FabrikTarget::Effector{ joint, effector: EndEffector::default(), transform: Transform::from_translation(translation).into() }
+    }
+
+    fn settings() -> IkChainSettings{
+        IkChainSettings{ iterations: Some(50), minimum_tolerance: Some(0.0001), ..default() }
+    }
+
+    fn bottom(chain: &FabrikChain, i: usize) -> Vec3{
+        let (joint, jt) = (chain.joints[i], chain.transforms[i]);
+        jt.translation - (jt.rotation * joint.visual_offset)
+    }
+
+    fn top(chain: &FabrikChain, i: usize) -> Vec3{
+        bottom(chain, i) + chain.joints[i].up(chain.transforms[i].rotation) * chain.joints[i].length
+    }
 
-                bk.joints.lock().unwrap().get_mut(&main_entity).unwrap().1 = main_transform;
-                seen.write().unwrap().insert(main_entity);
+    //every joint has to start where its parent ends
+    fn assert_connected(chain: &FabrikChain){
+        for i in 0..chain.len() {
+            if let Some(parent) = chain.parent(i) {
+                assert!(top(chain, parent).distance(bottom(chain, i)) < EPSILON, "joint {i} came off its parent");
             }
         }
     }
 
-    
-    loop {
-        let next: Arc<Mutex<Vec<Entity>>> = Arc::new(Mutex::new(Vec::new()));
-
-        current.par_iter_mut().for_each(|main_entity| {
-            let mut main_transform = bk.joints.lock().unwrap().get(main_entity).unwrap().1;
-            let main_joint = bk.joints.lock().unwrap().get(main_entity).unwrap().0;
-
-            if let Ok((_, (parent_maybe, child_maybe))) = hierarchy_q.get(*main_entity) {
-                if let Some(parent) = parent_maybe {
-                    let parent_transform = bk.joints.lock().unwrap().get(&parent.0).unwrap().1;
-                    let parent_joint = bk.joints.lock().unwrap().get(&parent.0).unwrap().0;
-                    let parent_identity = if let Ok(constraint) = constraint_q.get(parent.0){
-                        constraint.identity.normalize()
-                    } else {
-                        Quat::IDENTITY
-                    };
-                  
-                    
-                    //remove visual offset from translation
-                    let parent_real_t = parent_transform.translation - (parent_transform.rotation * parent_joint.visual_offset);
+    #[test]
+    fn reaches_a_reachable_target(){
+        let mut chain = straight_chain(3, joint());
+        let target = Vec3::new(1.5, 1.0, 0.5);
+        let state = chain.solve(&[reach(2, target)], settings());
+
+        assert!(state.converged);
+        assert!(top(&chain, 2).distance(target) < EPSILON);
+        assert!(bottom(&chain, 0).distance(Vec3::ZERO) < EPSILON);
+        assert!(chain.effector_states()[2][0].reached && chain.effector_states()[2][0].reachable);
+        assert_connected(&chain);
+    }
 
-                    let parent_top = parent_real_t + (parent_transform.rotation * Vec3::Y * parent_joint.length);
-                    let anchor_pos = parent_top + (parent_transform.rotation * main_joint.anchor_offset);
+    #[test]
+    fn points_at_an_unreachable_target(){
+        let mut chain = straight_chain(3, joint());
+        let target = Vec3::new(5.0, 0.0, 0.0);
+        let state = chain.solve(&[reach(2, target)], settings());
+
+        assert!((state.error - 2.0).abs() < EPSILON);
+        assert!(state.stalled && !state.converged);
+        assert!(!chain.effector_states()[2][0].reached && !chain.effector_states()[2][0].reachable);
+        assert!(bottom(&chain, 0).distance(Vec3::ZERO) < EPSILON);
+        assert!(top(&chain, 2).distance(Vec3::new(3.0, 0.0, 0.0)) < EPSILON);
+        assert_connected(&chain);
+    }
 
-                    //remove visual offset from here aswell
-                    let main_real_t = main_transform.translation - (main_transform.rotation * main_joint.visual_offset);
-                    let main_top = main_real_t + (main_transform.rotation * Vec3::Y * main_joint.length);
-                    
+    #[test]
+    fn measures_reachability_at_the_full_stretch(){
+        let stretchy = Joint{ max_stretch: 1.5, ..joint() };
+
+        //stretched all the way there, it was in reach
+        let mut chain = straight_chain(3, stretchy);
+        let state = chain.solve(&[reach(2, Vec3::new(0.0, 4.0, 0.0))], settings());
+        assert!(state.converged && !state.stalled);
+        assert!(chain.effector_states()[2][0].reached && chain.effector_states()[2][0].reachable);
+
+        //a stiff joint doesn't stretch all the way, the target is still in reach
+        let mut chain = straight_chain(3, Joint{ stretch_stiffness: 0.5, ..stretchy });
+        chain.solve(&[reach(2, Vec3::new(0.0, 4.0, 0.0))], settings());
+        assert!(!chain.effector_states()[2][0].reached && chain.effector_states()[2][0].reachable);
+
+        //stretched as far as it goes and still short, the stretch it already took doesn't make it reachable
+        let mut chain = straight_chain(3, stretchy);
+        let state = chain.solve(&[reach(2, Vec3::new(0.0, 5.0, 0.0))], settings());
+        assert!((state.error - 0.5).abs() < EPSILON);
+        assert!(state.stalled && !state.converged);
+        assert!(!chain.effector_states()[2][0].reachable);
+        assert!(chain.stretches().iter().all(|&stretch| stretch <= 1.5 + EPSILON));
+    }
 
-                    let up_dir = (main_top - anchor_pos).normalize();
+    #[test]
+    fn keeps_swings_within_asymmetric_limits(){
+        //swings a long way towards +z but hardly at all towards -z, and barely sideways so it stays in the yz plane
+        let constraint = RotationConstraint{ strength: 1.0, x: vec2(-0.2, 1.2), z: vec2(-0.05, 0.05), ..default() };
+        let swings = |target: Vec3| {
+            //the root can't lean, so the joints above it have to bend
+            let mut chain = straight_chain(3, joint());
+            chain.set_lock(0, JointLock::Rotation);
+            chain.set_constraint(1, Some(constraint));
+            chain.set_constraint(2, Some(constraint));
+            chain.solve(&[reach(2, target)], settings());
+            assert_connected(&chain);
+
+            let swings: Vec<f32> = (1..3).map(|i| {
+                let local_up = chain.transforms[i - 1].rotation.inverse() * chain.joints[i].up(chain.transforms[i].rotation);
+                local_up.z.atan2(local_up.y)
+            }).collect();
+            (swings, top(&chain, 2).distance(target))
+        };
+
+        //two swings of about 0.6 each
+        let (forwards, error) = swings(Vec3::new(0.0, 2.2, 1.5));
+        assert!(error < EPSILON);
+        assert!(forwards.iter().all(|swing| (-EPSILON..=1.2 + EPSILON).contains(swing)), "{forwards:?}");
+
+        //the same bend the other way only gets as far as the limit
+        let (backwards, error) = swings(Vec3::new(0.0, 2.2, -1.5));
+        assert!(error > 0.1);
+        assert!(backwards.iter().all(|swing| (swing + 0.2).abs() < EPSILON), "{backwards:?}");
+    }
 
-                    let main_forward = main_transform.local_z().as_vec3();
+    #[test]
+    fn clamps_twist_separately_from_swing(){
+        //twists a little one way and further the other, the swing limits are wide open
+        let constraint = RotationConstraint{ strength: 1.0, y: vec2(-0.2, 0.5), ..default() };
+        for (twist, limit) in [(1.0, 0.5), (-1.0, -0.2)] {
+            let mut chain = straight_chain(3, joint());
+            chain.set_constraint(2, Some(constraint));
+            //bent sideways and twisted, the twist has to come out the same whatever the swing
+            let rotation = Quat::from_rotation_x(0.6) * Quat::from_rotation_y(twist);
+            let end = Vec3::new(0.0, 2.0, 0.0) + rotation * Vec3::Y;
+            let effector = EndEffector{ rotation_weight: 1.0, ..default() };
+            let target = FabrikTarget::Effector{ joint: 2, effector, transform: Transform::from_translation(end).with_rotation(rotation).into() };
+            chain.solve(&[target], settings());
+
+            let relative = chain.transforms[1].rotation.inverse() * chain.transforms[2].rotation;
+            let (swing, relative_twist) = swing_twist_decomposition(relative, Vec3::Y);
+            let angle = 2.0 * relative_twist.y.atan2(relative_twist.w);
+            assert!((angle - limit).abs() < EPSILON, "twisted by {angle} instead of {limit}");
+            assert!((swing.angle_between(Quat::IDENTITY) - 0.6).abs() < EPSILON);
+            assert!(top(&chain, 2).distance(end) < EPSILON);
+        }
+    }
 
-                    if let Ok(constraint) = constraint_q.get(*main_entity){
-                        let local_up_dir = (constraint.identity.inverse() * up_dir).normalize();
-                        let local_forward_dir = (constraint.identity.inverse() * main_forward).normalize();
-                            
-                        let constrained_local_up = constrain_direction_ellipse(local_up_dir, parent_identity.inverse() * parent_transform.local_y().as_vec3(), f32::max(constraint.x.y, 0.0000001),f32::max( constraint.z.y, 0.0000001), constraint.strength);
+    #[test]
+    fn keeps_each_chains_convergence_to_itself(){
+        let mut app = app();
+        let (reaching, _) = spawn_chain(app.world_mut(), 3, Vec3::ZERO, Vec3::new(1.5, 1.0, 0.5));
+        let (short, _) = spawn_chain(app.world_mut(), 3, Vec3::new(10.0, 0.0, 0.0), Vec3::new(10.0, 8.0, 0.0));
+        for _ in 0..10 {
+            app.update();
+        }
 
-                        let constrained_local_forward = constrain_direction_cone(local_forward_dir, parent_identity.inverse() * parent_transform.local_z().as_vec3(), f32::max( constraint.y.y, 0.0000001), constraint.strength);
+        //the chain that can't get there doesn't keep the other from converging, or the other way round
+        let state = *app.world().get::<IkChainState>(reaching[0]).unwrap();
+        assert!(state.converged && state.error < EPSILON, "{state:?}");
+        let state = *app.world().get::<IkChainState>(short[0]).unwrap();
+        assert!(!state.converged && (state.error - 5.0).abs() < EPSILON, "{state:?}");
+    }
 
-                        let constrained_global_up = constraint.identity * constrained_local_up;
+    #[test]
+    fn blends_towards_the_solved_pose_by_its_weight(){
+        let mut app = app();
+        //the same chain three times over, fully solved, half blended and disabled
+        let chains: Vec<Vec<Entity>> = [0.0, 10.0, 20.0].into_iter().map(|x| {
+            spawn_chain(app.world_mut(), 3, Vec3::new(x, 0.0, 0.0), Vec3::new(x + 1.5, 1.0, 0.5)).0
+        }).collect();
+        let base = |app: &App, chain: &[Entity]| app.world().get::<BaseJoint>(chain[0]).unwrap().0;
+        let (weighted, disabled) = (base(&app, &chains[1]), base(&app, &chains[2]));
+        app.world_mut().entity_mut(weighted).insert(IkWeight(0.5));
+        app.world_mut().entity_mut(disabled).insert(IkDisabled);
+        app.update();
+
+        for i in 0..3 {
+            let transform = |chain: &[Entity]| *app.world().get::<Transform>(chain[i]).unwrap();
+            let (solved, blended, left) = (transform(&chains[0]), transform(&chains[1]), transform(&chains[2]));
+            let start = Vec3::Y * i as f32;
+
+            assert!(blended.rotation.angle_between(Quat::IDENTITY.slerp(solved.rotation, 0.5)) < EPSILON);
+            assert!((blended.translation - Vec3::X * 10.0).distance(start.lerp(solved.translation, 0.5)) < EPSILON);
+
+            assert!(left.rotation.angle_between(Quat::IDENTITY) < EPSILON);
+            assert!((left.translation - Vec3::X * 20.0).distance(start) < EPSILON);
+        }
+        assert!(app.world().get::<Transform>(chains[0][2]).unwrap().rotation.angle_between(Quat::IDENTITY) > 0.5);
+    }
 
-                        let constrained_global_forward = constraint.identity * constrained_local_forward;
+    #[test]
+    fn only_moves_the_joints_within_chain_length(){
+        let target = Vec3::new(1.0, 2.5, 0.5);
+        for chain_length in [Some(2), None] {
+            let mut chain = straight_chain(4, joint());
+            let effector = EndEffector{ chain_length, ..default() };
+            chain.solve(&[FabrikTarget::Effector{ joint: 3, effector, transform: Transform::from_translation(target).into() }], settings());
+
+            assert!(top(&chain, 3).distance(target) < EPSILON);
+            assert_connected(&chain);
+            //the two joints below the last two stay where they were, unless the whole chain is moved
+            let moved = (0..2).any(|i| chain.transforms[i].rotation.angle_between(Quat::IDENTITY) > EPSILON);
+            assert_eq!(moved, chain_length.is_none());
+        }
+    }
 
-                        let final_rot = Transform::IDENTITY.aligned_by(Vec3::Y, constrained_global_up, Vec3::Z, constrained_global_forward).rotation;
+    #[test]
+    fn averages_several_effectors_on_one_joint_by_weight(){
+        let (first, second) = (Vec3::new(1.5, 1.5, 0.0), Vec3::new(-0.5, 2.5, 0.5));
+        let target = |translation: Vec3, position_weight: f32| FabrikTarget::Effector{
+            joint: 2,
+            effector: EndEffector{ position_weight, ..default() },
+            transform: Transform::from_translation(translation).into(),
+        };
+        let mut chain = straight_chain(3, joint());
+        chain.solve(&[target(first, 1.0), target(second, 0.5)], settings());
+
+        //the second one pulls half as hard, and each one's state is kept on its own
+        let expected = (first + second * 0.5) / 1.5;
+        assert!(top(&chain, 2).distance(expected) < EPSILON);
+        let states = &chain.effector_states()[2];
+        assert_eq!(states.len(), 2);
+        assert!((states[0].error - expected.distance(first)).abs() < EPSILON);
+        assert!((states[1].error - expected.distance(second)).abs() < EPSILON);
+        assert_connected(&chain);
+    }
 
-                                                    
-                        if final_rot.dot(main_transform.rotation) < 0.0{
-                            main_transform.rotation = -final_rot;
-                        } else {
-                            main_transform.rotation = final_rot;
-                        }
-                        
-                    } else {
-                        let final_rot = Transform::IDENTITY.aligned_by(Vec3::Y, up_dir, Vec3::Z, main_forward).rotation;
-                        
-                        if final_rot.dot(main_transform.rotation) < 0.0{
-                            main_transform.rotation = -final_rot;
-                        } else {
-                            main_transform.rotation = final_rot;
-                        }
-                        
-                    }
+    #[test]
+    fn closes_a_loop_between_two_bases(){
+        //the last joint is held by a second base, the ones in between have to bridge the gap
+        let other_base = Vec3::new(1.5, 2.0, 0.0);
+        let mut chain = straight_chain(4, joint());
+        chain.set_base(3, Transform::from_translation(other_base).into(), 0.0, 1.0);
+        let state = chain.solve(&[reach(3, other_base + Vec3::X)], settings());
+
+        assert!(bottom(&chain, 0).distance(Vec3::ZERO) < EPSILON);
+        assert!(bottom(&chain, 3).distance(other_base) < EPSILON);
+        assert!(top(&chain, 3).distance(other_base + Vec3::X) < EPSILON);
+        assert!(state.error < EPSILON);
+        assert_connected(&chain);
+    }
 
-                    //add visual offset into main
-                    main_transform.translation = anchor_pos + (main_transform.rotation * main_joint.visual_offset);
-                    
-                    if let Ok((_, ee_joint)) = effector_joints.get(*main_entity) {
-                        let (_, ee_transform) = *bk.ends.read().unwrap().get(&ee_joint.0).unwrap();
-                        let dist = ee_transform.translation - main_transform.translation;
-                        *end_dists.lock().unwrap() += dist;
-                    }
+    #[test]
+    fn moves_the_base_within_its_radius_to_reach(){
+        //half a joint out of reach straight up
+        let target = Vec3::new(0.0, 3.5, 0.0);
+        for (radius, weight, moved) in [(1.0, 1.0, 0.5), (0.2, 1.0, 0.2), (1.0, 0.5, 0.25), (0.0, 1.0, 0.0)] {
+            let mut chain = straight_chain(3, joint());
+            chain.set_base(0, JointTransform::IDENTITY, radius, weight);
+            let state = chain.solve(&[reach(2, target)], settings());
+
+            assert!(bottom(&chain, 0).distance(Vec3::Y * moved) < EPSILON, "radius {radius} weight {weight} moved the base to {}", bottom(&chain, 0));
+            assert!((state.error - (0.5 - moved)).abs() < EPSILON);
+            assert_eq!(chain.effector_states()[2][0].reachable, moved == 0.5);
+            assert_connected(&chain);
+        }
+    }
 
-                    bk.joints.lock().unwrap().get_mut(main_entity).unwrap().1 = main_transform;
+    #[test]
+    fn stretches_and_squashes_within_limits_and_stiffness(){
+        let cases = [
+            (Joint{ max_stretch: 1.5, ..joint() }, 3.0, 1.5),
+            (Joint{ max_stretch: 4.0, stretch_stiffness: 0.5, ..joint() }, 3.0, 2.0),
+            (Joint{ min_stretch: 0.4, ..joint() }, 0.5, 0.5),
+            (Joint{ min_stretch: 0.75, ..joint() }, 0.5, 0.75),
+            (joint(), 3.0, 1.0),
+        ];
+        for (joint, distance, stretch) in cases {
+            let mut chain = straight_chain(1, joint);
+            let state = chain.solve(&[reach(0, Vec3::Y * distance)], settings());
+
+            //the stretch ends up in the scale along the bone, the length itself is left alone
+            assert!((chain.stretches()[0] - stretch).abs() < EPSILON, "{joint:?} stretched by {} instead of {stretch}", chain.stretches()[0]);
+            assert!(chain.transforms[0].scale.abs_diff_eq(vec3(1.0, stretch, 1.0), EPSILON));
+            assert_eq!(chain.joints[0].length, 1.0);
+            assert!((state.error - (distance - stretch).abs()).abs() < EPSILON);
+        }
+    }
 
-                    
-                    
-                }
-                if let Some(children) = child_maybe {
-                    for child in children.0.iter() {
-                        if !seen.read().unwrap().contains(child) {
-                            next.lock().unwrap().push(*child);
-                        }
-                    }
-                }
+    #[test]
+    fn reaches_as_far_as_its_scale_takes_it(){
+        //a chain at twice the size reaches twice as far, with the lengths still in local space
+        let scaled_chain = || {
+            let mut chain = FabrikChain::new();
+            let mut parent = None;
+            for i in 0..3 {
+                parent = chain.add_joint(joint(), Transform::from_xyz(0.0, 2.0 * i as f32, 0.0).with_scale(Vec3::splat(2.0)).into(), parent);
             }
-        });
-        {
-            current.clear();
-            let mut next_lock = next.lock().unwrap();
-            current.append(&mut next_lock);
+            chain.set_base(0, JointTransform::IDENTITY, 0.0, 1.0);
+            chain
+        };
+
+        let mut chain = scaled_chain();
+        let target = Vec3::new(3.0, 3.0, 1.0);
+        let state = chain.solve(&[reach(2, target)], settings());
+        assert!(state.error < EPSILON);
+        assert!(chain.effector_states()[2][0].reached && chain.effector_states()[2][0].reachable);
+        assert!(chain.transforms().iter().all(|jt| jt.scale.abs_diff_eq(Vec3::splat(2.0), EPSILON)));
+        assert_eq!(chain.joints[2].length, 1.0);
+
+        let mut chain = scaled_chain();
+        let state = chain.solve(&[reach(2, Vec3::Y * 7.0)], settings());
+        assert!((state.error - 1.0).abs() < EPSILON);
+        assert!(!chain.effector_states()[2][0].reachable);
+    }
+
+    #[test]
+    fn triggers_events_once_when_states_change(){
+        #[derive(Resource, Default)]
+        struct Fired(Vec<(&'static str, Entity)>);
+
+        let mut app = app();
+        app.init_resource::<Fired>();
+        app.add_observer(|event: On<IkTargetReached>, mut fired: ResMut<Fired>| fired.0.push(("reached", event.entity)));
+        app.add_observer(|event: On<IkTargetUnreachable>, mut fired: ResMut<Fired>| fired.0.push(("unreachable", event.entity)));
+        app.add_observer(|event: On<IkChainConverged>, mut fired: ResMut<Fired>| fired.0.push(("converged", event.entity)));
+        app.add_observer(|event: On<IkChainStalled>, mut fired: ResMut<Fired>| fired.0.push(("stalled", event.entity)));
+        let (reaching, reached) = spawn_chain(app.world_mut(), 3, Vec3::ZERO, Vec3::new(1.5, 1.0, 0.5));
+        let (short, unreachable) = spawn_chain(app.world_mut(), 3, Vec3::new(10.0, 0.0, 0.0), Vec3::new(10.0, 8.0, 0.0));
+        for _ in 0..10 {
+            app.update();
         }
 
-        if current.is_empty() {
-            break;
+        let mut fired = std::mem::take(&mut app.world_mut().resource_mut::<Fired>().0);
+        fired.sort();
+        let mut expected = vec![("converged", reaching[0]), ("reached", reached), ("stalled", short[0]), ("unreachable", unreachable)];
+        expected.sort();
+        assert_eq!(fired, expected);
+        assert!(app.world().get::<IkEffectorState>(reached).is_some_and(|state| state.reached && state.reachable));
+        assert!(app.world().get::<IkEffectorState>(unreachable).is_some_and(|state| !state.reached && !state.reachable));
+    }
+
+    #[test]
+    fn leaves_a_pinned_pole_joint_alone(){
+        let mut chain = straight_chain(3, joint());
+        chain.set_lock(2, JointLock::Pinned);
+        let before = chain.transforms[2];
+        let pole = FabrikTarget::Pole{ joint: 2, pole: FabrikPole::default(), transform: Transform::from_xyz(-1.0, 1.0, 1.0).into() };
+        chain.solve(&[pole], settings());
+
+        assert!(chain.transforms[2].translation.distance(before.translation) < EPSILON);
+        assert!(chain.transforms[2].rotation.angle_between(before.rotation) < EPSILON);
+    }
+
+    #[test]
+    fn keeps_hinges_in_their_plane_and_limits(){
+        let mut chain = straight_chain(3, joint());
+        let hinge = HingeConstraint{ axis: Vec3::X, min: -0.5, max: 0.5, strength: 1.0, ..default() };
+        chain.set_hinge(1, Some(hinge));
+        chain.set_hinge(2, Some(hinge));
+        //out of the hinge plane and further around than the limits allow
+        chain.solve(&[reach(2, Vec3::new(1.0, 0.0, 1.5))], settings());
+
+        for i in 1..3 {
+            let parent_rotation = chain.transforms[i - 1].rotation;
+            let up = chain.joints[i].up(chain.transforms[i].rotation);
+            let hinge_axis = parent_rotation * Vec3::X;
+            assert!(up.dot(hinge_axis).abs() < EPSILON, "joint {i} left the hinge plane");
+            let parent_up = chain.joints[i - 1].up(parent_rotation);
+            assert!(up.angle_between(parent_up) < 0.5 + EPSILON, "joint {i} bent past its limit");
+        }
+        assert_connected(&chain);
+    }
+
+    #[test]
+    fn bends_hinges_around_their_rest_pose(){
+        let mut chain = straight_chain(3, joint());
+        let identity = Quat::from_rotation_z(0.8);
+        let hinge = HingeConstraint{ identity, axis: Vec3::Z, min: -0.3, max: 0.3, strength: 1.0, ..default() };
+        chain.set_hinge(1, Some(hinge));
+        chain.set_hinge(2, Some(hinge));
+        //only reachable with both joints bent around to their rest pose
+        let target = Vec3::Y + identity * Vec3::Y + identity * identity * Vec3::Y;
+        let state = chain.solve(&[reach(2, target)], settings());
+
+        assert!(state.converged);
+        assert!(top(&chain, 2).distance(target) < EPSILON);
+        for i in 1..3 {
+            let rest_frame = chain.transforms[i - 1].rotation * identity;
+            let up = chain.joints[i].up(chain.transforms[i].rotation);
+            assert!(up.dot(rest_frame * Vec3::Z).abs() < EPSILON, "joint {i} left the hinge plane");
+            assert!(up.angle_between(rest_frame * Vec3::Y) < 0.3 + EPSILON, "joint {i} bent past its limit");
+        }
+        assert_connected(&chain);
+    }
+
+    #[test]
+    fn takes_on_the_rotation_weight_of_the_effector_rotation(){
+        //the effector only twists around the bone, so the rest pose swung onto the aim is the identity
+        let rotation = Quat::from_rotation_y(1.0);
+        for rotation_weight in [0.0, 0.5, 1.0] {
+            let mut chain = straight_chain(3, joint());
+            let effector = EndEffector{ rotation_weight, ..default() };
+            let target = FabrikTarget::Effector{ joint: 2, effector, transform: Transform::from_xyz(0.0, 3.0, 0.0).with_rotation(rotation).into() };
+            //no tolerance, so it runs every iteration
+            chain.solve(&[target], IkChainSettings{ minimum_tolerance: Some(0.0), ..settings() });
+
+            let twist = chain.transforms[2].rotation.angle_between(Quat::IDENTITY);
+            assert!((twist - rotation_weight).abs() < EPSILON, "rotation_weight {rotation_weight} twisted by {twist}");
+            assert!(top(&chain, 2).distance(Vec3::new(0.0, 3.0, 0.0)) < EPSILON);
+        }
+    }
+
+    #[test]
+    fn points_aim_effectors_at_their_target_without_moving_onto_it(){
+        //further away than the chain reaches, which doesn't matter when aiming
+        let target = Vec3::new(6.0, 4.0, -2.0);
+        let mut chain = straight_chain(3, joint());
+        let effector = EndEffector{ mode: EffectorMode::Aim(Vec3::Y), ..default() };
+        let aim = FabrikTarget::Effector{ joint: 2, effector, transform: Transform::from_translation(target).into() };
+        let state = chain.solve(&[aim], settings());
+
+        let aim_dir = chain.joints[2].up(chain.transforms[2].rotation);
+        assert!(aim_dir.angle_between(target - bottom(&chain, 2)) < EPSILON);
+        assert!(top(&chain, 2).distance(target) > 3.0);
+        assert!(state.error < EPSILON);
+        assert!(chain.effector_states()[2][0].reached && chain.effector_states()[2][0].reachable);
+        assert!(bottom(&chain, 0).distance(Vec3::ZERO) < EPSILON);
+        assert_connected(&chain);
+    }
+
+    #[test]
+    fn turns_aim_effectors_by_their_weight_whatever_the_iterations(){
+        let target = Vec3::new(3.0, 1.0, 0.0);
+        let effector = EndEffector{ mode: EffectorMode::Aim(Vec3::Y), position_weight: 0.5, ..default() };
+        for iterations in [1, 3, 10] {
+            let mut chain = straight_chain(2, joint());
+            let aim = FabrikTarget::Effector{ joint: 1, effector, transform: Transform::from_translation(target).into() };
+            chain.solve(&[aim], IkChainSettings{ iterations: Some(iterations), minimum_tolerance: Some(0.0), ..settings() });
+
+            //half way from where the axis pointed before solving, seen from where the joint is now
+            let target_dir = (target - bottom(&chain, 1)).normalize();
+            let expected = Vec3::Y.angle_between(target_dir) * 0.5;
+            let turned = chain.joints[1].up(chain.transforms[1].rotation).angle_between(target_dir);
+            assert!((turned - expected).abs() < EPSILON, "{iterations} iterations left {turned} to go instead of {expected}");
+        }
+    }
+
+    #[test]
+    fn bends_towards_poles_by_their_weight_whatever_the_iterations(){
+        //bent towards +z, with the end effector right on its top so only the pole moves it
+        let bend = Quat::from_rotation_x(0.5);
+        let knee = bend * Vec3::Y;
+        let end = Vec3::new(0.0, knee.y * 2.0, 0.0);
+        let pole = FabrikTarget::Pole{ joint: 2, pole: FabrikPole{ weight: 0.5, ..default() }, transform: Transform::from_xyz(-1.0, 1.0, 0.0).into() };
+        for iterations in [1, 3, 10] {
+            let mut chain = FabrikChain::new();
+            chain.add_joint(joint(), Transform::from_rotation(bend).into(), None);
+            chain.add_joint(joint(), Transform::from_translation(knee).with_rotation(bend.inverse()).into(), Some(0));
+            chain.add_joint(joint(), Transform::from_translation(end).into(), Some(1));
+            chain.set_base(0, JointTransform::IDENTITY, 0.0, 1.0);
+            chain.solve(&[reach(2, end + Vec3::Y), pole], IkChainSettings{ iterations: Some(iterations), minimum_tolerance: Some(0.0), ..settings() });
+
+            //half way from +z round to -x
+            let knee_dir = bottom(&chain, 1).reject_from_normalized(Vec3::Y).normalize();
+            let turned = knee_dir.angle_between(Vec3::Z);
+            assert!((turned - std::f32::consts::FRAC_PI_4).abs() < EPSILON, "{iterations} iterations turned the knee by {turned}");
+            assert!(knee_dir.x < 0.0);
         }
     }
-        
-    *end_dists.lock().unwrap()
 }
@@ -1,5 +1,54 @@
 use bevy::prelude::*;
-use super::JointTransform;
+use super::{Joint, JointTransform};
+
+//the joint's axes in world space for a given rotation, up is along the bone
+impl Joint {
+    pub fn up(self, rotation: Quat) -> Vec3 {
+        rotation * self.bone_axis
+    }
+    pub fn forward(self, rotation: Quat) -> Vec3 {
+        rotation * self.secondary_axis
+    }
+    pub fn right(self, rotation: Quat) -> Vec3 {
+        rotation * self.bone_axis.cross(self.secondary_axis)
+    }
+    //the rotation that points the bone axis along up_dir, with the secondary axis as close to forward_dir as possible
+    pub fn aligned(self, up_dir: Vec3, forward_dir: Vec3) -> Quat {
+        Transform::IDENTITY.aligned_by(self.bone_axis, up_dir, self.secondary_axis, forward_dir).rotation
+    }
+    pub fn can_stretch(self) -> bool {
+        self.min_stretch < 1.0 || self.max_stretch > 1.0
+    }
+    //the joint with its length and the part of its visual offset along the bone stretched
+    pub fn stretched(self, stretch: f32) -> Joint {
+        let axis = self.bone_axis.normalize_or_zero();
+        Joint{
+            length: self.length * stretch,
+            visual_offset: self.visual_offset + axis * axis.dot(self.visual_offset) * (stretch - 1.0),
+            ..self
+        }
+    }
+    //the joint at its size in the world, scale is its global scale and parent_scale the one its anchor offset is in
+    pub fn scaled(self, scale: Vec3, parent_scale: Vec3) -> Joint {
+        Joint{
+            length: self.length * (scale * self.bone_axis.normalize_or_zero()).length(),
+            visual_offset: scale * self.visual_offset,
+            anchor_offset: parent_scale * self.anchor_offset,
+            ..self
+        }
+    }
+    //the scale without the stretch written into it
+    pub fn rest_scale(self, scale: Vec3) -> Vec3 {
+        if self.can_stretch() { self.stretched_scale(scale, 1.0) } else { scale }
+    }
+    //the scale along the bone axis is worked out from the scale across it, so a stretch written last frame doesn't add up
+    pub fn stretched_scale(self, scale: Vec3, stretch: f32) -> Vec3 {
+        let along = self.bone_axis.normalize_or_zero().abs();
+        let across = Vec3::ONE - along;
+        let across_scale = if across.element_sum() > 0.0 { (scale * across).element_sum() / across.element_sum() } else { 1.0 };
+        scale * across + along * across_scale * stretch
+    }
+}
 
 impl JointTransform {
     pub fn local_x(self) -> Dir3 {
@@ -52,7 +101,7 @@ impl JointTransform {
     
 }
 
-pub fn rotation_averaging(quats: &Vec<Quat>, weights: &Vec<f32>, quality_count: usize, start_quat: Quat) -> Quat {
+pub fn rotation_averaging(quats: &[Quat], weights: &[f32], quality_count: usize, start_quat: Quat) -> Quat {
     let mut accum = Mat4::ZERO;
     for (i, quat) in quats.iter().enumerate() {
         let [x, y, z, w] = quat.to_array();
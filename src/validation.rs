@@ -0,0 +1,282 @@
+use std::fmt;
+
+use bevy::{ecs::entity::{EntityHashMap, EntityHashSet}, prelude::*};
+
+use crate::{bookkeeper, IkSystems, JointBookkeeping, JointParent};
+
+use super::{BaseJoint, EEJoint, EffectorJoint, Joint};
+
+//Checks the joint graph whenever it changes and keeps what it found in IkValidationReport, each new problem is also logged as a warning.
+//Meant for building rigs, the solver itself only skips what it can't solve (see IkInvalid).
+pub struct IkValidationPlugin;
+
+impl Plugin for IkValidationPlugin{
+    fn build(&self, app: &mut App) {
+        app.init_resource::<IkValidationReport>();
+        //has to see the dirty flag before build_chains clears it
+        app.add_systems(PostUpdate, validate_rig.run_if(rig_changed).before(bookkeeper::build_chains).in_set(IkSystems::Collect));
+    }
+}
+
+//one problem with a rig
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+pub enum IkRigIssue{
+    //the JointParents loop back around, joint is the lowest entity in the loop
+    Cycle{ joint: Entity },
+    //the joint's JointParent points at an entity that isn't a joint, so the joint never ends up in a chain
+    ParentNotJoint{ joint: Entity, parent: Entity },
+    //no joint in the tree has a BaseJoint, so it floats around instead of being pinned anywhere
+    NoBase{ root: Entity },
+    //more than one joint in the tree has a BaseJoint, which closes the joints between them into a loop.
+    //That's fine if it's meant to be, so it doesn't make the rig invalid
+    MultipleBases{ root: Entity, count: usize },
+    //the joint's EEJoint pointed at an entity without an EndEffector, which got a default one
+    EffectorNotEndEffector{ joint: Entity, effector: Entity },
+    //the joint's EEJoint points at an entity that doesn't exist, or whose EffectorJoint doesn't point back at the joint
+    DanglingEffector{ joint: Entity, effector: Entity },
+    //the joint's length is zero, negative or not finite
+    InvalidLength{ joint: Entity, length: f32 },
+}
+
+impl fmt::Display for IkRigIssue{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IkRigIssue::Cycle{ joint } => write!(f, "the JointParents of {joint} form a cycle, remove one of them so the joints form a tree"),
+            IkRigIssue::ParentNotJoint{ joint, parent } => write!(f, "the JointParent of {joint} points at {parent}, which has no Joint, add one or remove the JointParent"),
+            IkRigIssue::NoBase{ root } => write!(f, "the chain starting at {root} has no BaseJoint, give its root joint a Base to pin it to"),
+            IkRigIssue::MultipleBases{ root, count } => write!(f, "the chain starting at {root} has {count} BaseJoints, the joints between them are solved as a closed loop"),
+            IkRigIssue::EffectorNotEndEffector{ joint, effector } => write!(f, "the EEJoint on {joint} points at {effector}, which had no EndEffector and got a default one, add one to it if that's intended"),
+            IkRigIssue::DanglingEffector{ joint, effector } => write!(f, "the EEJoint on {joint} points at {effector}, which doesn't exist or isn't attached to {joint}, point it at a live end effector or remove it"),
+            IkRigIssue::InvalidLength{ joint, length } => write!(f, "{joint} has a length of {length}, joint lengths have to be greater than zero"),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "bevy_reflect", derive(Reflect))]
+#[cfg_attr(feature = "bevy_reflect", reflect(Resource))]
+pub struct IkValidationReport{
+    pub issues: Vec<IkRigIssue>,
+}
+
+impl IkRigIssue{
+    //issues that are only worth knowing about, they don't stop the rig from working as intended
+    pub fn is_informational(&self) -> bool{
+        matches!(self, IkRigIssue::MultipleBases{ .. })
+    }
+}
+
+impl IkValidationReport{
+    pub fn is_valid(&self) -> bool{
+        self.issues.iter().all(IkRigIssue::is_informational)
+    }
+}
+
+//the hooks mark the chains dirty on every hierarchy change, lengths and re-parenting show up as changes
+#[allow(clippy::type_complexity)]
+fn rig_changed(
+    joint_bookkeeper: Res<JointBookkeeping>,
+    changed_q: Query<(), Or<(Changed<Joint>, Changed<JointParent>, Changed<EEJoint>, Changed<BaseJoint>)>>,
+) -> bool{
+    joint_bookkeeper.dirty || !changed_q.is_empty()
+}
+
+#[allow(clippy::type_complexity)]
+pub fn validate_rig(
+    joints_q: Query<(Entity, &Joint, Option<&JointParent>, Option<&EEJoint>, Has<BaseJoint>)>,
+    effectors_q: Query<&EffectorJoint>,
+    joint_bookkeeper: Res<JointBookkeeping>,
+    mut report: ResMut<IkValidationReport>,
+){
+    let mut issues = Vec::new();
+
+    //sorted so the report doesn't shuffle around between runs
+    let mut joints: Vec<Entity> = joints_q.iter().map(|(entity, ..)| entity).collect();
+    joints.sort();
+
+    //walks up from every joint to find the root of its tree, None if it never gets to one
+    let mut roots: EntityHashMap<Option<Entity>> = EntityHashMap::default();
+    for joint in joints.iter() {
+        let mut path = Vec::new();
+        let mut on_path = EntityHashSet::default();
+        let mut current = *joint;
+        let root = loop {
+            if let Some(root) = roots.get(&current) {
+                break *root;
+            }
+            if !on_path.insert(current) {
+                let start = path.iter().position(|e| *e == current).unwrap_or(0);
+                let lowest = path[start..].iter().copied().min().unwrap_or(current);
+                issues.push(IkRigIssue::Cycle{ joint: lowest });
+                break None;
+            }
+            path.push(current);
+
+            let Ok((_, _, parent, _, _)) = joints_q.get(current) else { break None };
+            let Some(parent) = parent else { break Some(current) };
+            if !joints_q.contains(parent.0) {
+                issues.push(IkRigIssue::ParentNotJoint{ joint: current, parent: parent.0 });
+                break None;
+            }
+            current = parent.0;
+        };
+        for entity in path {
+            roots.insert(entity, root);
+        }
+    }
+
+    let mut trees: EntityHashMap<Vec<Entity>> = EntityHashMap::default();
+    for joint in joints.iter() {
+        if let Some(Some(root)) = roots.get(joint) {
+            trees.entry(*root).or_default().push(*joint);
+        }
+    }
+    let mut tree_roots: Vec<Entity> = trees.keys().copied().collect();
+    tree_roots.sort();
+
+    //any number of bases works, more than one is only mentioned in case it's an accident
+    for root in tree_roots {
+        let count = trees[&root].iter().filter(|joint| joints_q.get(**joint).is_ok_and(|(_, _, _, _, has_base)| has_base)).count();
+        match count {
+            0 => issues.push(IkRigIssue::NoBase{ root }),
+            1 => {}
+            _ => issues.push(IkRigIssue::MultipleBases{ root, count }),
+        }
+    }
+
+    for joint in joints.iter() {
+        let Ok((_, joint_c, _, ee_joint, _)) = joints_q.get(*joint) else { continue };
+        //EEJoint's hook can't attach an end effector that was already despawned, so the joint never gets it
+        if let Some(ee_joint) = ee_joint && !effectors_q.get(ee_joint.0).is_ok_and(|effector_joint| effector_joint.joint() == *joint) {
+            issues.push(IkRigIssue::DanglingEffector{ joint: *joint, effector: ee_joint.0 });
+        } else if let Some(ee_joint) = ee_joint && joint_bookkeeper.implicit_effectors.get(joint) == Some(&ee_joint.0) {
+            issues.push(IkRigIssue::EffectorNotEndEffector{ joint: *joint, effector: ee_joint.0 });
+        }
+        if !(joint_c.length > 0.0 && joint_c.length.is_finite()) {
+            issues.push(IkRigIssue::InvalidLength{ joint: *joint, length: joint_c.length });
+        }
+    }
+
+    //only what's new gets logged, so a broken rig doesn't spam every time it's touched
+    for issue in issues.iter().filter(|issue| !report.issues.contains(issue)) {
+        if issue.is_informational() {
+            info!("ik rig: {issue}");
+        } else {
+            warn!("ik rig: {issue}");
+        }
+    }
+
+    report.set_if_neq(IkValidationReport{ issues });
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::IkSolverPlugin;
+
+    fn app() -> App{
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, TransformPlugin, IkSolverPlugin, IkValidationPlugin));
+        app.update();
+        app
+    }
+
+    fn joint() -> Joint{
+        Joint{ length: 0.2, ..default() }
+    }
+
+    //a straight line of joints going up from a base
+    fn spawn_chain(world: &mut World, count: usize) -> Vec<Entity>{
+        let base = world.spawn(Transform::default()).id();
+        let mut joints = Vec::new();
+        for i in 0..count {
+            let mut entity = world.spawn((joint(), Transform::from_xyz(0.0, 0.2 * i as f32, 0.0)));
+            if let Some(parent) = joints.last() {
+                entity.insert(JointParent(*parent));
+            } else {
+                entity.insert(BaseJoint(base));
+            }
+            joints.push(entity.id());
+        }
+        joints
+    }
+
+    fn issues(app: &mut App) -> Vec<IkRigIssue>{
+        app.update();
+        app.world().resource::<IkValidationReport>().issues.clone()
+    }
+
+    #[test]
+    fn valid_chain_has_no_issues(){
+        let mut app = app();
+        spawn_chain(app.world_mut(), 3);
+        assert_eq!(issues(&mut app), vec![]);
+        assert!(app.world().resource::<IkValidationReport>().is_valid());
+    }
+
+    #[test]
+    fn reports_cycles(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 3);
+        app.world_mut().entity_mut(joints[0]).insert(JointParent(joints[2]));
+        let lowest = joints.iter().copied().min().unwrap();
+        assert_eq!(issues(&mut app), vec![IkRigIssue::Cycle{ joint: lowest }]);
+    }
+
+    #[test]
+    fn reports_parents_that_are_not_joints(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 2);
+        //JointChildren adds a Joint to the parent, so it has to be taken away afterwards
+        let child = app.world_mut().spawn((joint(), Transform::default(), JointParent(joints[1]))).id();
+        app.world_mut().entity_mut(joints[1]).remove::<Joint>();
+        assert!(issues(&mut app).contains(&IkRigIssue::ParentNotJoint{ joint: child, parent: joints[1] }));
+    }
+
+    #[test]
+    fn reports_trees_without_a_base(){
+        let mut app = app();
+        let root = app.world_mut().spawn((joint(), Transform::default())).id();
+        app.world_mut().spawn((joint(), Transform::default(), JointParent(root)));
+        assert_eq!(issues(&mut app), vec![IkRigIssue::NoBase{ root }]);
+    }
+
+    #[test]
+    fn reports_dangling_effectors(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 2);
+        let effector = app.world_mut().spawn(Transform::default()).id();
+        app.world_mut().despawn(effector);
+        app.world_mut().entity_mut(joints[1]).insert(EEJoint(effector));
+        assert_eq!(issues(&mut app), vec![IkRigIssue::DanglingEffector{ joint: joints[1], effector }]);
+    }
+
+    #[test]
+    fn mentions_trees_with_several_bases(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 3);
+        let base = app.world_mut().spawn(Transform::default()).id();
+        app.world_mut().entity_mut(joints[2]).insert(BaseJoint(base));
+        assert_eq!(issues(&mut app), vec![IkRigIssue::MultipleBases{ root: joints[0], count: 2 }]);
+        assert!(app.world().resource::<IkValidationReport>().is_valid());
+    }
+
+    #[test]
+    fn reports_effectors_that_were_not_end_effectors(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 2);
+        let effector = app.world_mut().spawn(Transform::default()).id();
+        app.world_mut().entity_mut(joints[1]).insert(EEJoint(effector));
+        assert_eq!(issues(&mut app), vec![IkRigIssue::EffectorNotEndEffector{ joint: joints[1], effector }]);
+        assert!(!app.world().resource::<IkValidationReport>().is_valid());
+    }
+
+    #[test]
+    fn reports_invalid_lengths(){
+        let mut app = app();
+        let joints = spawn_chain(app.world_mut(), 2);
+        app.world_mut().entity_mut(joints[1]).insert(Joint{ length: 0.0, ..default() });
+        assert_eq!(issues(&mut app), vec![IkRigIssue::InvalidLength{ joint: joints[1], length: 0.0 }]);
+    }
+}